@@ -60,7 +60,8 @@ fn main() {
         panic!("Failed to run m68kmake");
     }
 
-    // Patch m68kconf.h to enable instruction hook
+    // Patch m68kconf.h to enable the instruction hook, and separate reads so
+    // opcode fetches don't look like data reads to watchpoints
     println!("cargo:warning=Patching m68kconf.h...");
     let conf_path = musashi_dir.join("m68kconf.h");
     let contents = fs::read_to_string(&conf_path).unwrap();
@@ -68,6 +69,8 @@ fn main() {
         .map(|line| {
             if line.contains("M68K_INSTRUCTION_HOOK") {
                 "#define M68K_INSTRUCTION_HOOK OPT_ON"
+            } else if line.starts_with("#define M68K_SEPARATE_READS") {
+                "#define M68K_SEPARATE_READS OPT_ON"
             } else {
                 line
             }
@@ -94,6 +97,8 @@ fn main() {
         .header(musashi_dir.join("m68k.h").to_str().expect("Invalid path"))
         .blocklist_function("m68k_read_memory_.*")
         .blocklist_function("m68k_write_memory_.*")
+        .blocklist_function("m68k_read_immediate_.*")
+        .blocklist_function("m68k_read_pcrelative_.*")
         .generate()
         .expect("Unable to generate bindings");
 
@@ -0,0 +1,97 @@
+/* Stack backtraces.
+ *
+ * Walks the A6 chain built by LINK A6: each frame holds the caller's A6 and,
+ * above it, the return address.  Between frames the stack is also searched
+ * for A-line exception frames, whose stacked PC points at (or, once the
+ * Toolbox dispatcher has adjusted it, just past) the trap word, so calls
+ * into the ROM through the trap dispatcher show up as frames too.
+ */
+
+use crate::cpu::{areg, get_pc};
+use crate::memory::{peek_u16, peek_u32, rom_offset, RAM_SIZE};
+use crate::symbols;
+use crate::traps;
+
+const MAX_FRAMES: usize = 64;
+// How far above SP to search for trap frames when A6 doesn't bound the search
+const SCAN_LIMIT: u32 = 256;
+
+pub enum FrameKind {
+    Pc,
+    Link { frame: u32 },
+    Trap { opcode: u16 },
+}
+
+pub struct Frame {
+    pub kind: FrameKind,
+    pub pc: u32,
+}
+
+impl Frame {
+    pub fn describe(&self) -> String {
+        let location = symbols::format_address(self.pc);
+        match self.kind {
+            FrameKind::Pc => location,
+            FrameKind::Link { frame } => format!("{}  (A6 frame 0x{:06X})", location, frame),
+            FrameKind::Trap { opcode } => format!("{}  {}", location, traps::describe(opcode)),
+        }
+    }
+}
+
+fn is_code_address(addr: u32) -> bool {
+    addr & 1 == 0 && (addr < RAM_SIZE as u32 || rom_offset(addr).is_some())
+}
+
+fn is_stack_address(addr: u32) -> bool {
+    addr & 1 == 0 && addr != 0 && addr < RAM_SIZE as u32
+}
+
+/// The A-line call site a stacked return address belongs to, if any.
+fn trap_call_site(value: u32) -> Option<(u32, u16)> {
+    let addr = value & 0xFFFFFF;
+    if !is_code_address(addr) || addr < 2 {
+        return None;
+    }
+    [addr, addr - 2].into_iter()
+        .map(|site| (site, peek_u16(site)))
+        .find(|&(_, opcode)| traps::is_trap(opcode))
+}
+
+fn scan_traps(frames: &mut Vec<Frame>, from: u32, to: u32) {
+    let mut addr = from;
+    while addr + 4 <= to && frames.len() < MAX_FRAMES {
+        if let Some((site, opcode)) = trap_call_site(peek_u32(addr)) {
+            frames.push(Frame { kind: FrameKind::Trap { opcode }, pc: site });
+            // Skip the rest of the six-byte exception frame
+            addr += 4;
+        } else {
+            addr += 2;
+        }
+    }
+}
+
+/// Frames from the current PC outwards.
+pub fn backtrace() -> Vec<Frame> {
+    let mut frames = vec![Frame { kind: FrameKind::Pc, pc: get_pc() }];
+    let mut sp = areg(7);
+    let mut fp = areg(6);
+    while frames.len() < MAX_FRAMES {
+        let linked = is_stack_address(fp) && fp >= sp;
+        scan_traps(&mut frames, sp, if linked { fp } else { sp.wrapping_add(SCAN_LIMIT) });
+        if !linked {
+            break;
+        }
+        let caller_fp = peek_u32(fp);
+        let return_pc = peek_u32(fp + 4) & 0xFFFFFF;
+        if !is_code_address(return_pc) {
+            break;
+        }
+        frames.push(Frame { kind: FrameKind::Link { frame: fp }, pc: return_pc });
+        sp = fp + 8;
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+    frames
+}
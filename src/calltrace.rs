@@ -0,0 +1,277 @@
+/* File Manager and Resource Manager call tracer.
+ *
+ * When a traced trap is about to execute we decode its arguments (the
+ * parameter block in A0 for OS traps, the Pascal stack frame for Toolbox
+ * traps) and remember where it will return to.  When the CPU reaches that
+ * return address with the stack unwound we decode the results.
+ */
+
+use crate::cpu::{areg, dreg};
+use crate::memory::{ostype, peek_pstring, peek_u16, peek_u32, peek_u8};
+use crate::{symbols, traps};
+use lazy_static::lazy_static;
+use log::info;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<PendingCall>> = Mutex::new(Vec::new());
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Calls that never come back (autopop traps, longjmps) are dropped past this depth
+const MAX_PENDING: usize = 64;
+
+// Low-memory global holding the Resource Manager's last error
+const RES_ERR: u32 = 0xA60;
+
+// ParamBlockRec offsets
+const IO_RESULT: u32 = 16;
+const IO_NAME_PTR: u32 = 18;
+const IO_VREFNUM: u32 = 22;
+const IO_REFNUM: u32 = 24;
+const IO_PERMSSN: u32 = 27;
+const IO_MISC: u32 = 28;
+const IO_REQCOUNT: u32 = 36;
+const IO_ACTCOUNT: u32 = 40;
+const IO_POSMODE: u32 = 44;
+const IO_POSOFFSET: u32 = 46;
+const IO_FDIRINDEX: u32 = 28;
+const IO_FLFNDRINFO: u32 = 32;
+
+struct PendingCall {
+    opcode: u16,
+    return_pc: u32,
+    // Stack pointer at the trap; the call has returned once SP is back at or above it
+    sp: u32,
+    a0: u32,
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        PENDING.lock().unwrap().clear();
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Called from the instruction hook with the PC of the instruction about to run.
+pub fn on_instruction(pc: u32) {
+    let sp = areg(7);
+    {
+        let mut pending = PENDING.lock().unwrap();
+        if let Some(index) = pending.iter().rposition(|c| c.return_pc == pc && sp >= c.sp) {
+            let call = pending.remove(index);
+            // Anything entered after this call and still open has been abandoned
+            pending.truncate(index);
+            drop(pending);
+            log_return(&call);
+        }
+    }
+
+    let opcode = peek_u16(pc);
+    if !traps::is_trap(opcode) || !is_traced(opcode) {
+        return;
+    }
+    let call = PendingCall { opcode, return_pc: pc.wrapping_add(2), sp, a0: areg(0) };
+    log_entry(&call, pc);
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() >= MAX_PENDING {
+        pending.remove(0);
+    }
+    pending.push(call);
+}
+
+fn is_traced(opcode: u16) -> bool {
+    if traps::is_toolbox(opcode) {
+        matches!(
+            traps::canonical(opcode),
+            0xA81F | 0xA820 | 0xA997 | 0xA998 | 0xA99A | 0xA99D | 0xA9A0 | 0xA9A1 | 0xA9A2
+                | 0xA9A3 | 0xA9AB | 0xA9AD | 0xA9B1 | 0xA9C4
+        )
+    } else {
+        matches!(opcode & 0xFF, 0x00..=0x03 | 0x07..=0x15 | 0x17 | 0x18 | 0x41..=0x45)
+    }
+}
+
+fn name_at(ptr: u32) -> String {
+    if ptr == 0 {
+        "NIL".to_string()
+    } else {
+        format!("\"{}\"", peek_pstring(ptr))
+    }
+}
+
+fn log_entry(call: &PendingCall, pc: u32) {
+    let name = traps::describe(call.opcode);
+    let args = if traps::is_toolbox(call.opcode) {
+        resource_args(call)
+    } else {
+        file_args(call)
+    };
+    info!("FS {}  {}  {}", symbols::format_address(pc), name, args);
+}
+
+fn file_args(call: &PendingCall) -> String {
+    let pb = call.a0;
+    let name = name_at(peek_u32(pb.wrapping_add(IO_NAME_PTR)));
+    let vref = peek_u16(pb.wrapping_add(IO_VREFNUM)) as i16;
+    let refnum = peek_u16(pb.wrapping_add(IO_REFNUM)) as i16;
+    match call.opcode & 0xFF {
+        // _Open, _OpenRF
+        0x00 | 0x0A => format!("pb=0x{:06X} name={} vRefNum={} perm={}", pb, name, vref, peek_u8(pb.wrapping_add(IO_PERMSSN))),
+        // _Read, _Write
+        0x02 | 0x03 => format!(
+            "pb=0x{:06X} refNum={} reqCount={} posMode={} posOffset={}",
+            pb, refnum, peek_u32(pb.wrapping_add(IO_REQCOUNT)), peek_u16(pb.wrapping_add(IO_POSMODE)), peek_u32(pb.wrapping_add(IO_POSOFFSET)) as i32
+        ),
+        // _Close, _GetEOF, _GetFPos, _FlushFile
+        0x01 | 0x11 | 0x18 | 0x45 => format!("pb=0x{:06X} refNum={}", pb, refnum),
+        // _SetEOF, _Allocate
+        0x10 | 0x12 => format!("pb=0x{:06X} refNum={} count={}", pb, refnum, peek_u32(pb.wrapping_add(IO_MISC))),
+        // _SetFPos
+        0x44 => format!(
+            "pb=0x{:06X} refNum={} posMode={} posOffset={}",
+            pb, refnum, peek_u16(pb.wrapping_add(IO_POSMODE)), peek_u32(pb.wrapping_add(IO_POSOFFSET)) as i32
+        ),
+        // _Rename
+        0x0B => format!("pb=0x{:06X} name={} newName={} vRefNum={}", pb, name, name_at(peek_u32(pb.wrapping_add(IO_MISC))), vref),
+        // _GetFileInfo, _SetFileInfo
+        0x0C | 0x0D => format!(
+            "pb=0x{:06X} name={} vRefNum={} index={}",
+            pb, name, vref, peek_u16(pb.wrapping_add(IO_FDIRINDEX)) as i16
+        ),
+        _ => format!("pb=0x{:06X} name={} vRefNum={}", pb, name, vref),
+    }
+}
+
+fn resource_args(call: &PendingCall) -> String {
+    let sp = call.sp;
+    match traps::canonical(call.opcode) {
+        // _GetResource, _Get1Resource
+        0xA9A0 | 0xA81F => format!("type='{}' id={}", ostype(peek_u32(sp.wrapping_add(2))), peek_u16(sp) as i16),
+        // _GetNamedResource, _Get1NamedResource
+        0xA9A1 | 0xA820 => format!("type='{}' name={}", ostype(peek_u32(sp.wrapping_add(4))), name_at(peek_u32(sp))),
+        // _GetIndResource
+        0xA99D => format!("type='{}' index={}", ostype(peek_u32(sp.wrapping_add(2))), peek_u16(sp) as i16),
+        // _OpenResFile, _CreateResFile
+        0xA997 | 0xA9B1 => format!("name={}", name_at(peek_u32(sp))),
+        // _OpenRFPerm
+        0xA9C4 => format!(
+            "name={} vRefNum={} perm={}",
+            name_at(peek_u32(sp.wrapping_add(4))), peek_u16(sp.wrapping_add(2)) as i16, peek_u8(sp)
+        ),
+        // _UseResFile, _CloseResFile
+        0xA998 | 0xA99A => format!("refNum={}", peek_u16(sp) as i16),
+        // _AddResource
+        0xA9AB => format!(
+            "handle=0x{:06X} type='{}' id={} name={}",
+            peek_u32(sp.wrapping_add(10)), ostype(peek_u32(sp.wrapping_add(6))), peek_u16(sp.wrapping_add(4)) as i16, name_at(peek_u32(sp))
+        ),
+        // _LoadResource, _ReleaseResource, _RmveResource
+        _ => format!("handle=0x{:06X}", peek_u32(sp)),
+    }
+}
+
+fn log_return(call: &PendingCall) {
+    let name = traps::describe(call.opcode);
+    let results = if traps::is_toolbox(call.opcode) {
+        resource_results(call)
+    } else {
+        file_results(call)
+    };
+    info!("FS            {}  -> {}", name, results);
+}
+
+fn file_results(call: &PendingCall) -> String {
+    let pb = call.a0;
+    let result = peek_u16(pb.wrapping_add(IO_RESULT)) as i16;
+    let status = format!("ioResult={} ({}) D0={}", result, error_name(result), dreg(0) as i16);
+    match call.opcode & 0xFF {
+        0x00 | 0x0A => format!("{} refNum={}", status, peek_u16(pb.wrapping_add(IO_REFNUM)) as i16),
+        0x02 | 0x03 => format!("{} actCount={} posOffset={}", status, peek_u32(pb.wrapping_add(IO_ACTCOUNT)), peek_u32(pb.wrapping_add(IO_POSOFFSET))),
+        0x11 => format!("{} eof={}", status, peek_u32(pb.wrapping_add(IO_MISC))),
+        0x18 => format!("{} pos={}", status, peek_u32(pb.wrapping_add(IO_POSOFFSET))),
+        0x0C => format!(
+            "{} name={} type='{}' creator='{}'",
+            status, name_at(peek_u32(pb.wrapping_add(IO_NAME_PTR))),
+            ostype(peek_u32(pb.wrapping_add(IO_FLFNDRINFO))), ostype(peek_u32(pb.wrapping_add(IO_FLFNDRINFO + 4)))
+        ),
+        0x14 => format!("{} name={} vRefNum={}", status, name_at(peek_u32(pb.wrapping_add(IO_NAME_PTR))), peek_u16(pb.wrapping_add(IO_VREFNUM)) as i16),
+        _ => status,
+    }
+}
+
+fn resource_results(call: &PendingCall) -> String {
+    let res_err = peek_u16(RES_ERR) as i16;
+    let status = format!("ResErr={} ({})", res_err, error_name(res_err));
+    // Function results sit where the caller reserved space, just above the arguments
+    let sp = call.sp;
+    match traps::canonical(call.opcode) {
+        0xA9A0 | 0xA81F | 0xA99D => format!("handle=0x{:06X} {}", peek_u32(sp.wrapping_add(6)), status),
+        0xA9A1 | 0xA820 => format!("handle=0x{:06X} {}", peek_u32(sp.wrapping_add(8)), status),
+        0xA997 => format!("refNum={} {}", peek_u16(sp.wrapping_add(4)) as i16, status),
+        0xA9C4 => format!("refNum={} {}", peek_u16(sp.wrapping_add(8)) as i16, status),
+        _ => status,
+    }
+}
+
+/// Names the common File, Device and Resource Manager result codes.
+pub fn error_name(code: i16) -> &'static str {
+    match code {
+        0 => "noErr",
+        -17 => "controlErr",
+        -18 => "statusErr",
+        -19 => "readErr",
+        -20 => "writErr",
+        -21 => "badUnitErr",
+        -22 => "unitEmptyErr",
+        -23 => "openErr",
+        -24 => "closErr",
+        -25 => "dRemovErr",
+        -26 => "dInstErr",
+        -27 => "abortErr",
+        -28 => "notOpenErr",
+        -33 => "dirFulErr",
+        -34 => "dskFulErr",
+        -35 => "nsvErr",
+        -36 => "ioErr",
+        -37 => "bdNamErr",
+        -38 => "fnOpnErr",
+        -39 => "eofErr",
+        -40 => "posErr",
+        -42 => "tmfoErr",
+        -43 => "fnfErr",
+        -44 => "wPrErr",
+        -45 => "fLckdErr",
+        -46 => "vLckdErr",
+        -47 => "fBsyErr",
+        -48 => "dupFNErr",
+        -49 => "opWrErr",
+        -50 => "paramErr",
+        -51 => "rfNumErr",
+        -52 => "gfpErr",
+        -53 => "volOffLinErr",
+        -54 => "permErr",
+        -55 => "volOnLinErr",
+        -56 => "nsDrvErr",
+        -57 => "noMacDskErr",
+        -58 => "extFSErr",
+        -59 => "fsRnErr",
+        -60 => "badMDBErr",
+        -61 => "wrPermErr",
+        -108 => "memFullErr",
+        -109 => "nilHandleErr",
+        -192 => "resNotFound",
+        -193 => "resFNotFound",
+        -194 => "addResFailed",
+        -196 => "rmvResFailed",
+        -198 => "resAttrErr",
+        -199 => "mapReadErr",
+        _ => "?",
+    }
+}
@@ -0,0 +1,260 @@
+/* Code coverage collection.
+ *
+ * One bit per word of ROM and RAM records whether an instruction started
+ * there.  Coverage is saved as a sorted `rom OFFSET` / `ram ADDRESS` list so
+ * two runs can be compared with diff, or as an annotated ROM listing.
+ */
+
+use crate::cpu::disassemble;
+use crate::symbols;
+use crate::memory::{peek_u16, rom_offset, RAM_SIZE, ROM_BASE, ROM_SIZE};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref COVERAGE: Mutex<Coverage> = Mutex::new(Coverage::new());
+    static ref OUTPUT: Mutex<Option<String>> = Mutex::new(None);
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Executed RAM code further apart than this starts a new block in the listing
+const RAM_BLOCK_GAP: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Location {
+    Rom(u32),
+    Ram(u32),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Rom(offset) => write!(f, "rom {:06X}", offset),
+            Location::Ram(addr) => write!(f, "ram {:06X}", addr),
+        }
+    }
+}
+
+struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    fn new(bytes: usize) -> Self {
+        // One bit per 16-bit word
+        Bitmap { words: vec![0; bytes / 2 / 64 + 1] }
+    }
+
+    fn set(&mut self, offset: u32) {
+        let bit = (offset / 2) as usize;
+        self.words[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn get(&self, offset: u32) -> bool {
+        let bit = (offset / 2) as usize;
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn offsets(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64).filter(move |b| word & (1 << b) != 0).map(move |b| ((i * 64 + b) * 2) as u32)
+        })
+    }
+
+    fn clear(&mut self) {
+        self.words.iter_mut().for_each(|w| *w = 0);
+    }
+}
+
+struct Coverage {
+    rom: Bitmap,
+    // Allocated on first use; a bit per word of 16MB is 1MB
+    ram: Option<Bitmap>,
+}
+
+impl Coverage {
+    fn new() -> Self {
+        Coverage { rom: Bitmap::new(ROM_SIZE), ram: None }
+    }
+
+    fn locations(&self) -> Vec<Location> {
+        let mut locations: Vec<Location> = self.rom.offsets().map(Location::Rom).collect();
+        if let Some(ram) = &self.ram {
+            locations.extend(ram.offsets().map(Location::Ram));
+        }
+        locations
+    }
+}
+
+pub fn start() {
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("Coverage collection started");
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+    info!("Coverage collection stopped");
+}
+
+pub fn reset() {
+    let mut coverage = COVERAGE.lock().unwrap();
+    coverage.rom.clear();
+    coverage.ram = None;
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes `<path>` (address list) and `<path>.lst` (annotated ROM listing) on shutdown.
+pub fn set_output(path: &str) {
+    *OUTPUT.lock().unwrap() = Some(path.to_string());
+}
+
+/// Marks the instruction at `pc` as executed.
+pub fn record(pc: u32) {
+    let mut coverage = COVERAGE.lock().unwrap();
+    if let Some(offset) = rom_offset(pc) {
+        coverage.rom.set(offset);
+    } else if pc < RAM_SIZE as u32 {
+        coverage.ram.get_or_insert_with(|| Bitmap::new(RAM_SIZE)).set(pc);
+    }
+}
+
+fn parse_location(line: &str) -> Option<Location> {
+    let (kind, addr) = line.trim().split_once(' ')?;
+    let addr = u32::from_str_radix(addr.trim(), 16).ok()?;
+    match kind {
+        "rom" => Some(Location::Rom(addr)),
+        "ram" => Some(Location::Ram(addr)),
+        _ => None,
+    }
+}
+
+/// Writes every executed instruction address, one per line, in sorted order.
+pub fn write_addresses(path: &str) -> io::Result<()> {
+    let locations = COVERAGE.lock().unwrap().locations();
+    let mut out = BufWriter::new(File::create(path)?);
+    for location in locations {
+        writeln!(out, "{}", location)?;
+    }
+    out.flush()
+}
+
+fn read_addresses(path: &str) -> io::Result<BTreeSet<Location>> {
+    Ok(fs::read_to_string(path)?.lines().filter_map(parse_location).collect())
+}
+
+/// Compares the current run with a saved address list: (only in this run, only in the file).
+pub fn compare(path: &str) -> io::Result<(Vec<Location>, Vec<Location>)> {
+    let saved = read_addresses(path)?;
+    let current: BTreeSet<Location> = COVERAGE.lock().unwrap().locations().into_iter().collect();
+    Ok((
+        current.difference(&saved).copied().collect(),
+        saved.difference(&current).copied().collect(),
+    ))
+}
+
+/// Disassembles the ROM, plus executed blocks of RAM, marking executed instructions with `*`.
+pub fn write_listing(path: &str) -> io::Result<()> {
+    let coverage = COVERAGE.lock().unwrap();
+    let mut out = BufWriter::new(File::create(path)?);
+    let rom_hits = coverage.rom.offsets().count();
+    let ram_hits = coverage.ram.as_ref().map_or(0, |ram| ram.offsets().count());
+    writeln!(out, "; Coverage listing: {} ROM and {} RAM instructions executed", rom_hits, ram_hits)?;
+    writeln!(out, "\n; ROM")?;
+    write_range(&mut out, &coverage.rom, ROM_BASE, 0, ROM_SIZE as u32)?;
+
+    if let Some(ram) = &coverage.ram {
+        let hits: Vec<u32> = ram.offsets().collect();
+        let mut i = 0;
+        while i < hits.len() {
+            let start = hits[i];
+            while i + 1 < hits.len() && hits[i + 1] - hits[i] <= RAM_BLOCK_GAP {
+                i += 1;
+            }
+            let (_, last_len) = disassemble(hits[i]);
+            writeln!(out, "\n; RAM block 0x{:06X}", start)?;
+            write_range(&mut out, ram, 0, start, hits[i] + last_len.max(2))?;
+            i += 1;
+        }
+    }
+    out.flush()
+}
+
+/// Linear sweep over `[start, end)`, resynchronising on executed addresses so
+/// data decoded as code never swallows a real instruction.
+fn write_range(out: &mut dyn Write, bitmap: &Bitmap, base: u32, start: u32, end: u32) -> io::Result<()> {
+    let mut offset = start;
+    while offset < end {
+        let addr = base + offset;
+        let executed = bitmap.get(offset);
+        let (text, len) = disassemble(addr);
+        let len = len.max(2);
+        let overlaps_hit = (2..len).step_by(2).any(|i| offset + i < end && bitmap.get(offset + i));
+        if !executed && overlaps_hit {
+            writeln!(out, "  {:06X}    dc.w ${:04X}", addr, peek_u16(addr))?;
+            offset += 2;
+            continue;
+        }
+        if let Some(name) = symbols::exact(addr) {
+            writeln!(out, "{}:", name)?;
+        }
+        writeln!(out, "{} {:06X}    {}", if executed { '*' } else { ' ' }, addr, text)?;
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Writes the configured output files, if any.
+pub fn finish() {
+    let Some(path) = OUTPUT.lock().unwrap().clone() else {
+        return;
+    };
+    if let Err(e) = write_addresses(&path) {
+        warn!("Failed to write coverage to {}: {}", path, e);
+    }
+    let listing = format!("{}.lst", path);
+    if let Err(e) = write_listing(&listing) {
+        warn!("Failed to write coverage listing to {}: {}", listing, e);
+    }
+    info!("Coverage written to {} and {}", path, listing);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_tracks_words() {
+        let mut bitmap = Bitmap::new(0x200);
+        bitmap.set(0);
+        bitmap.set(0x7E);
+        bitmap.set(0x81);
+        assert!(bitmap.get(0x80));
+        assert!(!bitmap.get(0x7C));
+        assert_eq!(bitmap.offsets().collect::<Vec<_>>(), [0, 0x7E, 0x80]);
+        bitmap.clear();
+        assert_eq!(bitmap.offsets().count(), 0);
+    }
+
+    #[test]
+    fn locations_round_trip() {
+        let mut coverage = Coverage::new();
+        coverage.rom.set(0x1A2);
+        coverage.ram.get_or_insert_with(|| Bitmap::new(0x1000)).set(0x800);
+        let lines: Vec<String> = coverage.locations().iter().map(|l| l.to_string()).collect();
+        assert_eq!(lines, ["rom 0001A2", "ram 000800"]);
+        let parsed: Vec<Location> = lines.iter().filter_map(|l| parse_location(l)).collect();
+        assert_eq!(parsed, [Location::Rom(0x1A2), Location::Ram(0x800)]);
+        assert_eq!(parse_location("bus 000100"), None);
+        assert_eq!(parse_location("rom xyz"), None);
+    }
+}
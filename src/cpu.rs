@@ -1,40 +1,135 @@
-use crate::memory::{read_u8, read_u16, write_u8, write_u16, read_u32, write_u32};
+use crate::memory::{read_u8, read_u16, write_u8, write_u16, read_u32, write_u32, peek_u8, peek_u16, peek_u32};
+use crate::{calltrace, coverage, crash, debugger, events, patches, profiler, segments, shadow, symbols, traps, watchdog, watchpoint};
 use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::ffi::CStr;
-use std::sync::atomic::Ordering;
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+fn check_read(address: u32, size: u32, value: u32) {
+    if watchpoint::enabled() {
+        watchpoint::check_read(address, size, value, get_ppc());
+    }
+    if shadow::enabled() {
+        shadow::check_read(address, size, get_ppc());
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn m68k_read_memory_8(address: u32) -> u8 {
-    read_u8(address)
+    let value = read_u8(address);
+    check_read(address, 1, value as u32);
+    value
 }
 #[no_mangle]
 pub extern "C" fn m68k_read_memory_16(address: u32) -> u16 {
-    read_u16(address)
+    let value = read_u16(address);
+    check_read(address, 2, value as u32);
+    value
 }
 #[no_mangle]
 pub extern "C" fn m68k_write_memory_8(address: u32, value: u8) {
-    write_u8(address, value)
+    if watchpoint::enabled() {
+        watchpoint::check_write(address, 1, peek_u8(address) as u32, value as u32, get_ppc());
+    }
+    if shadow::enabled() {
+        shadow::on_write(address, 1);
+    }
+    write_u8(address, value);
+    // Either half of DSErrCode; report the whole word as it now stands
+    if address & !1 == events::DS_ERR_CODE {
+        events::on_ds_err_code(peek_u16(events::DS_ERR_CODE) as i16);
+    }
 }
 #[no_mangle]
 pub extern "C" fn m68k_write_memory_16(address: u32, value: u16) {
+    if watchpoint::enabled() {
+        watchpoint::check_write(address, 2, peek_u16(address) as u32, value as u32, get_ppc());
+    }
+    if shadow::enabled() {
+        shadow::on_write(address, 2);
+    }
+    if address == events::DS_ERR_CODE {
+        events::on_ds_err_code(value as i16);
+    }
     write_u16(address, value)
 }
 
 #[no_mangle]
 pub extern "C" fn m68k_read_memory_32(address: u32) -> u32 {
+    let value = read_u32(address);
+    check_read(address, 4, value);
+    value
+}
+
+// Opcode and extension word fetches (M68K_SEPARATE_READS); not data reads
+#[no_mangle]
+pub extern "C" fn m68k_read_immediate_16(address: u32) -> u32 {
+    read_u16(address) as u32
+}
+#[no_mangle]
+pub extern "C" fn m68k_read_immediate_32(address: u32) -> u32 {
     read_u32(address)
 }
 
+// PC-relative operands are data that happens to sit in the code
+#[no_mangle]
+pub extern "C" fn m68k_read_pcrelative_8(address: u32) -> u32 {
+    m68k_read_memory_8(address) as u32
+}
+#[no_mangle]
+pub extern "C" fn m68k_read_pcrelative_16(address: u32) -> u32 {
+    m68k_read_memory_16(address) as u32
+}
+#[no_mangle]
+pub extern "C" fn m68k_read_pcrelative_32(address: u32) -> u32 {
+    m68k_read_memory_32(address)
+}
+
 #[no_mangle]
 pub extern "C" fn m68k_write_memory_32(address: u32, value: u32) {
+    if watchpoint::enabled() {
+        watchpoint::check_write(address, 4, peek_u32(address), value, get_ppc());
+    }
+    if shadow::enabled() {
+        shadow::on_write(address, 4);
+    }
+    if address == events::DS_ERR_CODE {
+        events::on_ds_err_code((value >> 16) as i16);
+    } else if address == events::DS_ERR_CODE - 2 {
+        events::on_ds_err_code(value as i16);
+    }
     write_u32(address, value)
 }
 
+/// 68000 clock of the 128K/512K/Plus, in cycles per second.
+pub const CLOCK_HZ: u64 = 7_833_600;
+
+// Set by the instruction hook; tells a halted CPU apart from one that ran
+static INSTRUCTION_STARTED: AtomicBool = AtomicBool::new(false);
+
 #[no_mangle]
 pub extern "C" fn instruction_hook_callback(address: u32) {
-    //info!("Executing instruction at: 0x{:X} {}", address, disassemble_instruction(address));
+    INSTRUCTION_STARTED.store(true, Ordering::Relaxed);
+    let opcode = peek_u16(address);
+    events::before_instruction(address, opcode);
+    segments::before_instruction(address, opcode);
+    if traps::is_trap(opcode) {
+        patches::before_trap();
+    }
+    if shadow::enabled() {
+        shadow::before_instruction(address, opcode);
+    }
+    if traps::logging() {
+        traps::record_call(address);
+    }
+    if calltrace::enabled() {
+        calltrace::on_instruction(address);
+    }
+    if coverage::enabled() {
+        coverage::record(address);
+    }
+    //info!("Executing instruction at: 0x{:X} {}", address, disassemble(address).0);
     // info!("Bytes: {:02X} {:02X} {:02X} {:02X}", 
     //     read_u8(address),
     //     read_u8(address + 1),
@@ -59,15 +154,13 @@ pub fn init() {
 }
 
 pub fn step(cycles: i32) -> i32 {
-    use crate::memory::{SINGLE_STEP, wait_for_keypress_hw};
     let mut cycles_left = cycles;
     let mut total_cycles = 0;
     while cycles_left > 0 {
-        if SINGLE_STEP.load(Ordering::SeqCst) {
-            let pc = get_pc();
-            let _ = wait_for_keypress_hw("Single-step", pc);
+        if debugger::check_break(get_pc()) {
+            break;
         }
-        let executed = unsafe { m68k_execute(1) };
+        let executed = execute_instruction();
         if executed <= 0 {
             break;
         }
@@ -77,6 +170,34 @@ pub fn step(cycles: i32) -> i32 {
     total_cycles
 }
 
+/// Runs exactly one instruction, turning any watchpoint hit into a debugger stop.
+pub fn execute_instruction() -> i32 {
+    let pc = get_pc();
+    let isp = get_isp();
+    INSTRUCTION_STARTED.store(false, Ordering::Relaxed);
+    let executed = unsafe { m68k_execute(1) };
+    let new_pc = get_pc();
+    crash::after_instruction(pc, isp, new_pc, INSTRUCTION_STARTED.load(Ordering::Relaxed));
+    if profiler::enabled() {
+        profiler::record(pc, new_pc, executed);
+    }
+    if watchdog::enabled() {
+        watchdog::record(pc, executed);
+    }
+    if let Some(hit) = watchpoint::take_hit() {
+        debugger::request_break(&hit.to_string());
+    }
+    executed
+}
+
+/// JSR, BSR, TRAP #n and A-line traps all return to the following instruction.
+pub fn is_call_instruction(opcode: u16) -> bool {
+    (opcode & 0xFFC0) == 0x4E80
+        || (opcode & 0xFF00) == 0x6100
+        || (opcode & 0xFFF0) == 0x4E40
+        || (opcode & 0xF000) == 0xA000
+}
+
 pub fn get_reg(reg: m68k_register_t) -> u32 {
     unsafe { m68k_get_reg(core::ptr::null_mut(), reg) }
 }
@@ -85,36 +206,96 @@ pub fn get_pc() -> u32 {
     unsafe { m68k_get_reg(core::ptr::null_mut(), m68k_register_t_M68K_REG_PC) }
 }
 
-pub fn disassemble_instruction(pc: u32) -> String {
+pub fn set_pc(addr: u32) {
+    set_reg(m68k_register_t_M68K_REG_PC, addr)
+}
+
+pub fn get_sr() -> u32 {
+    get_reg(m68k_register_t_M68K_REG_SR)
+}
+
+/// Supervisor stack pointer, whichever mode the CPU is in.
+pub fn get_isp() -> u32 {
+    get_reg(m68k_register_t_M68K_REG_ISP)
+}
+
+pub fn dreg(n: usize) -> u32 {
+    get_reg(m68k_register_t_M68K_REG_D0 + n as m68k_register_t)
+}
+
+pub fn areg(n: usize) -> u32 {
+    get_reg(m68k_register_t_M68K_REG_A0 + n as m68k_register_t)
+}
+
+/// Address of the instruction currently (or most recently) executing.
+pub fn get_ppc() -> u32 {
+    unsafe { m68k_get_reg(core::ptr::null_mut(), m68k_register_t_M68K_REG_PPC) }
+}
+
+/// Disassembles one instruction, returning its text and length in bytes.
+pub fn disassemble(pc: u32) -> (String, u32) {
+    let opcode = peek_u16(pc);
+    if traps::is_trap(opcode) {
+        return (traps::describe(opcode), 2);
+    }
     let mut buffer = [0u8; 100];
-    let mut pc_copy = pc;
     unsafe {
-        m68k_disassemble(buffer.as_mut_ptr() as *mut i8, pc_copy, M68K_CPU_TYPE_68000);
-        CStr::from_ptr(buffer.as_ptr() as *const i8)
+        let len = m68k_disassemble(buffer.as_mut_ptr() as *mut i8, pc, M68K_CPU_TYPE_68000);
+        let text = CStr::from_ptr(buffer.as_ptr() as *const i8)
             .to_string_lossy()
-            .into_owned()
+            .into_owned();
+        (symbols::annotate(&text), len)
     }
 }
 
 pub fn display_registers() {
     println!("\nRegisters:");
-    println!("PC: 0x{:08X}", get_reg(m68k_register_t_M68K_REG_PC));
-    println!("SR: 0x{:04X}", get_reg(m68k_register_t_M68K_REG_SR));
-    println!("D0: 0x{:08X}  D1: 0x{:08X}  D2: 0x{:08X}  D3: 0x{:08X}", 
-        get_reg(m68k_register_t_M68K_REG_D0), get_reg(m68k_register_t_M68K_REG_D1), 
-        get_reg(m68k_register_t_M68K_REG_D2), get_reg(m68k_register_t_M68K_REG_D3));
-    println!("D4: 0x{:08X}  D5: 0x{:08X}  D6: 0x{:08X}  D7: 0x{:08X}", 
-        get_reg(m68k_register_t_M68K_REG_D4), get_reg(m68k_register_t_M68K_REG_D5), 
-        get_reg(m68k_register_t_M68K_REG_D6), get_reg(m68k_register_t_M68K_REG_D7));
-    println!("A0: 0x{:08X}  A1: 0x{:08X}  A2: 0x{:08X}  A3: 0x{:08X}", 
-        get_reg(m68k_register_t_M68K_REG_A0), get_reg(m68k_register_t_M68K_REG_A1), 
-        get_reg(m68k_register_t_M68K_REG_A2), get_reg(m68k_register_t_M68K_REG_A3));
-    println!("A4: 0x{:08X}  A5: 0x{:08X}  A6: 0x{:08X}  A7: 0x{:08X}", 
-        get_reg(m68k_register_t_M68K_REG_A4), get_reg(m68k_register_t_M68K_REG_A5), 
-        get_reg(m68k_register_t_M68K_REG_A6), get_reg(m68k_register_t_M68K_REG_A7));
-    println!();
-}
-
-// pub fn set_reg(reg: m68k_register_t, value: u32) {
-//     unsafe { m68k_set_reg(reg, value) }
-// }
+    println!("{}", register_dump());
+}
+
+/// PC, SR and the data and address registers, one line per group.
+pub fn register_dump() -> String {
+    let mut out = format!("PC: 0x{:08X}\n", get_reg(m68k_register_t_M68K_REG_PC));
+    out += &format!("SR: 0x{:04X}\n", get_reg(m68k_register_t_M68K_REG_SR));
+    for (name, base) in [("D", m68k_register_t_M68K_REG_D0), ("A", m68k_register_t_M68K_REG_A0)] {
+        for row in [0, 4] {
+            let regs: Vec<String> = (row..row + 4)
+                .map(|n| format!("{}{}: 0x{:08X}", name, n, get_reg(base + n as m68k_register_t)))
+                .collect();
+            out += &regs.join("  ");
+            out.push('\n');
+        }
+    }
+    out
+}
+
+pub fn set_reg(reg: m68k_register_t, value: u32) {
+    unsafe { m68k_set_reg(reg, value) }
+}
+
+pub fn reg_by_name(name: &str) -> Option<m68k_register_t> {
+    let reg = match name.to_ascii_lowercase().as_str() {
+        "d0" => m68k_register_t_M68K_REG_D0,
+        "d1" => m68k_register_t_M68K_REG_D1,
+        "d2" => m68k_register_t_M68K_REG_D2,
+        "d3" => m68k_register_t_M68K_REG_D3,
+        "d4" => m68k_register_t_M68K_REG_D4,
+        "d5" => m68k_register_t_M68K_REG_D5,
+        "d6" => m68k_register_t_M68K_REG_D6,
+        "d7" => m68k_register_t_M68K_REG_D7,
+        "a0" => m68k_register_t_M68K_REG_A0,
+        "a1" => m68k_register_t_M68K_REG_A1,
+        "a2" => m68k_register_t_M68K_REG_A2,
+        "a3" => m68k_register_t_M68K_REG_A3,
+        "a4" => m68k_register_t_M68K_REG_A4,
+        "a5" => m68k_register_t_M68K_REG_A5,
+        "a6" => m68k_register_t_M68K_REG_A6,
+        "a7" | "sp" => m68k_register_t_M68K_REG_A7,
+        "pc" => m68k_register_t_M68K_REG_PC,
+        "sr" => m68k_register_t_M68K_REG_SR,
+        "usp" => m68k_register_t_M68K_REG_USP,
+        "ssp" | "isp" => m68k_register_t_M68K_REG_ISP,
+        _ => return None,
+    };
+    Some(reg)
+}
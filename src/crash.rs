@@ -0,0 +1,217 @@
+/* Exception and crash diagnostics.
+ *
+ * After every instruction we look for signs that the CPU took an exception:
+ * the supervisor stack grew by an exception frame (6 bytes, or 14 for bus
+ * and address errors) and the new PC is a handler in the vector table.
+ * Error exceptions are logged with their frame; a bus or address error
+ * taken before the previous one's handler returned is a double fault.
+ * The CPU not starting an instruction when it isn't sitting after a STOP
+ * means Musashi has halted it, and a run of zero or $FFFF opcodes, or of
+ * code fetched from I/O space, means execution has run away.  Any of these
+ * stops the machine with a diagnostic report: registers, recent PCs and a
+ * backtrace.
+ */
+
+use crate::backtrace;
+use crate::cpu::{disassemble, get_isp, get_sr, register_dump};
+use crate::debugger;
+use crate::events::{self, MachineEvent};
+use crate::memory::{peek_u16, peek_u32, ram_end, rom_offset};
+use crate::symbols::format_address;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+lazy_static! {
+    static ref REPORT_PATH: Mutex<Option<String>> = Mutex::new(None);
+}
+
+static BREAK_ON_EXCEPTION: AtomicBool = AtomicBool::new(true);
+
+// Updated after every instruction, so kept in atomics rather than behind a lock
+static TRACE: [AtomicU32; TRACE_LEN] = [const { AtomicU32::new(0) }; TRACE_LEN];
+// Total instructions traced; the next slot is TRACE_POS % TRACE_LEN
+static TRACE_POS: AtomicUsize = AtomicUsize::new(0);
+// Set while a bus or address error handler is running
+static IN_FAULT: AtomicBool = AtomicBool::new(false);
+static RUNAWAY: AtomicU32 = AtomicU32::new(0);
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+const TRACE_LEN: usize = 64;
+// Consecutive suspicious instructions before execution counts as runaway
+const RUNAWAY_LIMIT: u32 = 16;
+const STOP: u16 = 0x4E72;
+const RTE: u16 = 0x4E73;
+const SR_SUPERVISOR: u32 = 0x2000;
+
+/// The exception a vector number stands for.
+pub fn vector_name(vector: u32) -> &'static str {
+    match vector {
+        2 => "bus error",
+        3 => "address error",
+        4 => "illegal instruction",
+        5 => "zero divide",
+        6 => "CHK",
+        7 => "TRAPV",
+        8 => "privilege violation",
+        9 => "trace",
+        10 => "A-line trap",
+        11 => "F-line trap",
+        15 => "uninitialized interrupt",
+        24 => "spurious interrupt",
+        25..=31 => "interrupt autovector",
+        32..=47 => "TRAP #n",
+        _ => "reserved",
+    }
+}
+
+fn is_error(vector: u32) -> bool {
+    !matches!(vector, 10 | 25..=47)
+}
+
+/// Where the report is also written when the machine crashes.
+pub fn set_report_path(path: &str) {
+    *REPORT_PATH.lock().unwrap() = Some(path.to_string());
+}
+
+/// Picks the vector that sent the CPU to `new_pc`, using the faulting opcode to break ties.
+fn find_vector(pc: u32, new_pc: u32, frame_size: u32) -> Option<u32> {
+    let opcode = peek_u16(pc);
+    let hint = match opcode {
+        _ if frame_size == 14 => None,
+        0x4AFC => Some(4),
+        _ if opcode & 0xF000 == 0xA000 => Some(10),
+        _ if opcode & 0xF000 == 0xF000 => Some(11),
+        _ if opcode & 0xFFF0 == 0x4E40 => Some(32 + (opcode & 0xF) as u32),
+        _ => None,
+    };
+    let candidates: Vec<u32> = if frame_size == 14 { vec![2, 3] } else { (4..48).collect() };
+    let matches: Vec<u32> = candidates.into_iter().filter(|&v| peek_u32(v * 4) & 0xFFFFFF == new_pc).collect();
+    match hint {
+        Some(v) if matches.contains(&v) => Some(v),
+        _ => matches.first().copied(),
+    }
+}
+
+/// Whether error exceptions stop the machine in the debugger.
+pub fn set_break_on_exception(enabled: bool) {
+    BREAK_ON_EXCEPTION.store(enabled, Ordering::Relaxed);
+}
+
+pub fn break_on_exception() -> bool {
+    BREAK_ON_EXCEPTION.load(Ordering::Relaxed)
+}
+
+/// Forgets the trace and any fault or crash seen before a reset.
+pub fn reset() {
+    TRACE_POS.store(0, Ordering::Relaxed);
+    IN_FAULT.store(false, Ordering::Relaxed);
+    RUNAWAY.store(0, Ordering::Relaxed);
+    CRASHED.store(false, Ordering::Relaxed);
+}
+
+/// Checks the instruction that just ran from `pc`; `isp` is the supervisor SP before it
+/// and `ran` whether the CPU actually started an instruction.
+pub fn after_instruction(pc: u32, isp: u32, new_pc: u32, ran: bool) {
+    let slot = TRACE_POS.fetch_add(1, Ordering::Relaxed) % TRACE_LEN;
+    TRACE[slot].store(pc, Ordering::Relaxed);
+
+    let opcode = peek_u16(pc);
+    if opcode == RTE {
+        IN_FAULT.store(false, Ordering::Relaxed);
+    }
+
+    if !ran {
+        if peek_u16(pc.wrapping_sub(4)) == STOP || CRASHED.load(Ordering::Relaxed) {
+            return;
+        }
+        crash(&format!("CPU halted at {} (double bus fault?)", format_address(pc)));
+        return;
+    }
+
+    // The ISP is A7 once in supervisor mode, so only a frame-sized drop needs SR checked
+    let sp = get_isp();
+    let frame_size = isp.wrapping_sub(sp);
+    if (frame_size == 6 || frame_size == 14) && get_sr() & SR_SUPERVISOR != 0 {
+        if let Some(vector) = find_vector(pc, new_pc, frame_size) {
+            let double = frame_size == 14 && IN_FAULT.load(Ordering::Relaxed);
+            if frame_size == 14 {
+                IN_FAULT.store(true, Ordering::Relaxed);
+            }
+            log_exception(vector, pc, sp, frame_size);
+            if double {
+                crash(&format!("Double fault: {} inside a bus/address error handler", vector_name(vector)));
+            } else if is_error(vector) && break_on_exception() {
+                debugger::request_break(&format!("Exception: {} (vector {}) at {}", vector_name(vector), vector, format_address(pc)));
+            }
+            return;
+        }
+    }
+
+    let mapped = rom_offset(new_pc).is_some() || new_pc < ram_end();
+    let next = peek_u16(new_pc);
+    if !mapped || next == 0x0000 || next == 0xFFFF {
+        if RUNAWAY.fetch_add(1, Ordering::Relaxed) + 1 == RUNAWAY_LIMIT {
+            crash(&format!("Runaway execution at {} (opcode ${:04X})", format_address(new_pc), next));
+        }
+    } else {
+        RUNAWAY.store(0, Ordering::Relaxed);
+    }
+}
+
+fn log_exception(vector: u32, pc: u32, sp: u32, frame_size: u32) {
+    let sr = peek_u16(sp + frame_size - 6);
+    let stacked_pc = peek_u32(sp + frame_size - 4);
+    let detail = if frame_size == 14 {
+        // Group 0 frame: function code word, access address, instruction register
+        format!(
+            " access 0x{:06X} ({}, FC {}) IR ${:04X}",
+            peek_u32(sp + 2),
+            if peek_u16(sp) & 0x10 != 0 { "read" } else { "write" },
+            peek_u16(sp) & 7,
+            peek_u16(sp + 6)
+        )
+    } else {
+        String::new()
+    };
+    let message = format!(
+        "Exception {} ({}) at {}: stacked SR ${:04X} PC 0x{:06X}, frame at 0x{:06X}{}",
+        vector, vector_name(vector), format_address(pc), sr, stacked_pc, sp, detail
+    );
+    if is_error(vector) {
+        warn!("{}", message);
+    } else {
+        debug!("{}", message);
+    }
+}
+
+/// Registers, the last instructions executed and a backtrace.
+pub fn report(reason: &str) -> String {
+    let mut out = format!("=== Crash report: {} ===\n\nRegisters:\n{}", reason, register_dump());
+    out += "\nRecent instructions:\n";
+    let end = TRACE_POS.load(Ordering::Relaxed);
+    for pos in end.saturating_sub(TRACE_LEN)..end {
+        let pc = TRACE[pos % TRACE_LEN].load(Ordering::Relaxed);
+        out += &format!("  {:<28} {}\n", format_address(pc), disassemble(pc).0);
+    }
+    out += "\nBacktrace:\n";
+    for (i, frame) in backtrace::backtrace().iter().enumerate() {
+        out += &format!("  #{:<3} {}\n", i, frame.describe());
+    }
+    out
+}
+
+fn crash(reason: &str) {
+    CRASHED.store(true, Ordering::Relaxed);
+    events::emit(MachineEvent::Crash { reason: reason.to_string() });
+    let report = report(reason);
+    eprintln!("{}", report);
+    if let Some(path) = REPORT_PATH.lock().unwrap().as_ref() {
+        if let Err(e) = fs::write(path, &report) {
+            warn!("Failed to write crash report to {}: {}", path, e);
+        }
+    }
+    debugger::request_break(reason);
+}
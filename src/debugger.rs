@@ -0,0 +1,942 @@
+use crate::cpu::{
+    disassemble, display_registers, execute_instruction, get_pc, get_reg, is_call_instruction,
+    reg_by_name, set_pc, set_reg,
+};
+use crate::memory::{
+    iwm_state, load_memory, parse_address, peek_u16, peek_u32, peek_u8, poke_u8, save_memory, save_ram, ROM_BASE,
+    ROM_SIZE,
+};
+use crate::symbols::{self, format_address, parse_symbolic};
+use crate::via::VIA;
+use crate::{backtrace, calltrace, coverage, crash, drivers, files, gdbstub, heap, lowmem, macsbug, memsearch, patches, queues, resources, segments, shadow, watchdog, windows, profiler, traps, watchpoint};
+use lazy_static::lazy_static;
+use log::info;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::new());
+    static ref CONSOLE: Mutex<Option<Receiver<String>>> = Mutex::new(None);
+}
+
+// Checked before every instruction; set while a stop is pending or breakpoints exist
+static ATTENTION: AtomicBool = AtomicBool::new(false);
+// Cleared for headless runs with nobody to resume a stopped machine
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Breakpoint {
+    Address(u32),
+    Trap(u16),
+}
+
+impl Breakpoint {
+    fn describe(&self) -> String {
+        match *self {
+            Breakpoint::Address(addr) => format_address(addr),
+            Breakpoint::Trap(word) => traps::describe(word),
+        }
+    }
+}
+
+struct State {
+    paused: bool,
+    pending: Option<String>,
+    breakpoints: Vec<Breakpoint>,
+    // One-shot stop used by step-over and run-to
+    temp_break: Option<u32>,
+    history: Vec<String>,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            paused: false,
+            pending: None,
+            breakpoints: Vec::new(),
+            temp_break: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn update_attention(&self) {
+        let attention = self.pending.is_some() || !self.breakpoints.is_empty() || self.temp_break.is_some();
+        ATTENTION.store(attention, Ordering::Relaxed);
+    }
+}
+
+/// Reads console lines on a background thread so the window keeps running.
+pub fn start_console() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    *CONSOLE.lock().unwrap() = Some(rx);
+}
+
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+/// Asks the CPU loop to stop before the next instruction.
+pub fn request_break(reason: &str) {
+    if !INTERACTIVE.load(Ordering::Relaxed) {
+        info!("Not stopping (headless): {}", reason);
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    if state.pending.is_none() {
+        state.pending = Some(reason.to_string());
+    }
+    ATTENTION.store(true, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+    STATE.lock().unwrap().paused
+}
+
+/// Called before each instruction; returns true if the CPU should stop at `pc`.
+pub fn check_break(pc: u32) -> bool {
+    if !ATTENTION.load(Ordering::Relaxed) {
+        return false;
+    }
+    let mut state = STATE.lock().unwrap();
+    let reason = if let Some(reason) = state.pending.take() {
+        reason
+    } else if state.temp_break == Some(pc) {
+        state.temp_break = None;
+        format!("Stopped at {}", format_address(pc))
+    } else if state.breakpoints.contains(&Breakpoint::Address(pc)) {
+        format!("Breakpoint at {}", format_address(pc))
+    } else if let Some(Breakpoint::Trap(word)) = trap_breakpoint(&state.breakpoints, pc) {
+        format!("Trap breakpoint {} at {}", traps::describe(word), format_address(pc))
+    } else {
+        return false;
+    };
+    state.paused = true;
+    state.update_attention();
+    drop(state);
+    println!("\n{}", reason);
+    show_location();
+    prompt();
+    gdbstub::notify_stop();
+    true
+}
+
+fn trap_breakpoint(breakpoints: &[Breakpoint], pc: u32) -> Option<Breakpoint> {
+    let opcode = peek_u16(pc);
+    if !traps::is_trap(opcode) {
+        return None;
+    }
+    let word = traps::canonical(opcode);
+    breakpoints.iter().copied().find(|&bp| bp == Breakpoint::Trap(word))
+}
+
+fn push_breakpoint(bp: Breakpoint) -> usize {
+    let mut state = STATE.lock().unwrap();
+    state.breakpoints.push(bp);
+    state.update_attention();
+    state.breakpoints.len()
+}
+
+pub(crate) fn add_breakpoint(addr: u32) -> usize {
+    push_breakpoint(Breakpoint::Address(addr))
+}
+
+pub(crate) fn remove_breakpoint(addr: u32) -> bool {
+    let mut state = STATE.lock().unwrap();
+    let Some(index) = state.breakpoints.iter().position(|&bp| bp == Breakpoint::Address(addr)) else {
+        return false;
+    };
+    state.breakpoints.remove(index);
+    state.update_attention();
+    true
+}
+
+pub(crate) fn take_pending() -> Option<String> {
+    let mut state = STATE.lock().unwrap();
+    let pending = state.pending.take();
+    state.update_attention();
+    pending
+}
+
+/// Runs any console commands typed since the last frame.
+pub fn poll() {
+    let lines: Vec<String> = match CONSOLE.lock().unwrap().as_ref() {
+        Some(rx) => rx.try_iter().collect(),
+        None => return,
+    };
+    for line in lines {
+        run_command(&line);
+    }
+}
+
+fn prompt() {
+    if is_paused() {
+        print!("> ");
+        io::stdout().flush().unwrap();
+    }
+}
+
+fn show_location() {
+    display_registers();
+    let pc = get_pc();
+    if let Some(sym) = symbols::symbolize(pc) {
+        println!("{}:", sym);
+    }
+    println!("  0x{:06X}  {}", pc, disassemble(pc).0);
+}
+
+/// Resolves `!!`, `!N` and the empty line (repeat last) against the history.
+fn expand_history(line: &str) -> Option<String> {
+    let state = STATE.lock().unwrap();
+    if line.is_empty() || line == "!!" {
+        return Some(state.history.last().cloned().unwrap_or_default());
+    }
+    if let Some(n) = line.strip_prefix('!') {
+        let n: usize = n.parse().ok()?;
+        return state.history.get(n.checked_sub(1)?).cloned();
+    }
+    Some(line.to_string())
+}
+
+fn record_history(line: &str) {
+    let mut state = STATE.lock().unwrap();
+    if state.history.last().map(String::as_str) != Some(line) {
+        state.history.push(line.to_string());
+        if state.history.len() > HISTORY_LIMIT {
+            state.history.remove(0);
+        }
+    }
+}
+
+fn run_command(line: &str) {
+    let line = match expand_history(line.trim()) {
+        Some(line) => line,
+        None => {
+            println!("No such history entry");
+            prompt();
+            return;
+        }
+    };
+    if line.is_empty() {
+        prompt();
+        return;
+    }
+    record_history(&line);
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let paused = is_paused();
+    match (args[0], paused) {
+        ("h" | "help" | "?", _) => print_help(),
+        ("history", _) => {
+            for (i, entry) in STATE.lock().unwrap().history.iter().enumerate() {
+                println!("{:4}  {}", i + 1, entry);
+            }
+        }
+        ("q" | "quit", _) => {
+            crate::shutdown();
+            std::process::exit(0);
+        }
+        ("stop" | "pause", false) => request_break("Stopped from console"),
+        ("s" | "step", true) => {
+            let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+            step_instructions(count);
+        }
+        ("n" | "next", true) => step_over(),
+        ("c" | "continue", true) => {
+            resume();
+        }
+        ("u" | "until", true) => match args.get(1).and_then(|a| parse_symbolic(a)) {
+            Some(addr) => {
+                set_temp_break(addr);
+                resume();
+            }
+            None => println!("usage: until ADDR"),
+        },
+        ("r" | "regs", _) => registers(&args[1..]),
+        ("m" | "mem", _) => match args.get(1).and_then(|a| parse_symbolic(a)) {
+            Some(addr) => {
+                let len = args.get(2).and_then(|l| parse_address(l)).unwrap_or(64);
+                dump_memory(addr, len);
+            }
+            None => println!("usage: mem ADDR [LEN]"),
+        },
+        ("e" | "edit", _) => edit_memory(&args[1..]),
+        ("poke", _) => match args[1..] {
+            [addr, size, value] => match (parse_symbolic(addr), size_arg(size)) {
+                (Some(addr), Some(size)) => match sized_value(value, size) {
+                    Some(value) => {
+                        if !memsearch::write(addr, size, value) {
+                            println!("0x{:06X} is not RAM", addr);
+                        }
+                    }
+                    None => println!("Bad {}-byte value '{}'", size, value),
+                },
+                _ => println!("usage: poke ADDR b|w|l VALUE"),
+            },
+            _ => println!("usage: poke ADDR b|w|l VALUE"),
+        },
+        ("search", _) => search_command(&args[1..]),
+        ("dump", _) if args.get(1) == Some(&"ram") => match args.get(2) {
+            Some(path) => match save_ram(path) {
+                Ok(len) => println!("{} bytes of RAM written to {}", len, path),
+                Err(e) => println!("Failed to write {}: {}", path, e),
+            },
+            None => println!("usage: dump ram FILE"),
+        },
+        ("dump", _) => {
+            let range = match args[1..] {
+                ["rom", path] => Some((ROM_BASE, ROM_SIZE as u32, path)),
+                [addr, len, path] => parse_symbolic(addr).zip(parse_address(len)).map(|(addr, len)| (addr, len, path)),
+                _ => None,
+            };
+            match range {
+                Some((addr, len, path)) => match save_memory(path, addr, len) {
+                    Ok(()) => println!("{} bytes from 0x{:06X} written to {}", len, addr, path),
+                    Err(e) => println!("Failed to write {}: {}", path, e),
+                },
+                None => println!("usage: dump ram|rom FILE, dump ADDR LEN FILE"),
+            }
+        }
+        ("load", _) => match (args.get(1), args.get(2).and_then(|a| parse_symbolic(a))) {
+            (Some(path), Some(addr)) => match load_memory(path, addr) {
+                Ok(len) => {
+                    println!("{} bytes loaded at 0x{:06X}", len, addr);
+                    if args.get(3) == Some(&"go") {
+                        set_pc(addr);
+                        println!("PC set to 0x{:06X}", addr);
+                    }
+                }
+                Err(e) => println!("Failed to load {}: {}", path, e),
+            },
+            _ => println!("usage: load FILE ADDR [go]"),
+        },
+        ("shadow", _) => match args.get(1).copied() {
+            Some("on") => shadow::start(false),
+            Some("off") => shadow::stop(),
+            Some("list") => {
+                for (pc, addr, count) in shadow::reads() {
+                    println!("  {}  first 0x{:06X}  {} reads", format_address(pc), addr, count);
+                }
+            }
+            _ => println!("Uninitialized read detection is {}", if shadow::enabled() { "on" } else { "off" }),
+        },
+        ("freeze", _) => match args[1..] {
+            [] => {
+                for (i, f) in memsearch::freezes().iter().enumerate() {
+                    println!("{:3}  {}  = 0x{:0w$X}", i + 1, format_address(f.addr), f.value, w = f.size as usize * 2);
+                }
+            }
+            [addr, size] | [addr, size, _] => match (parse_symbolic(addr), size_arg(size)) {
+                (Some(addr), Some(size)) => {
+                    let value = match args.get(3) {
+                        Some(v) => sized_value(v, size),
+                        None => Some(memsearch::read(addr, size)),
+                    };
+                    match value {
+                        Some(value) => memsearch::freeze(addr, size, value),
+                        None => println!("Bad {}-byte value '{}'", size, args[3]),
+                    }
+                }
+                _ => println!("usage: freeze ADDR b|w|l [VALUE]"),
+            },
+            _ => println!("usage: freeze ADDR b|w|l [VALUE]"),
+        },
+        ("unfreeze", _) => match args.get(1).copied() {
+            Some("all") => memsearch::unfreeze_all(),
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 && memsearch::unfreeze(n - 1) => {}
+                _ => println!("No freeze {}", n),
+            },
+            None => println!("usage: unfreeze N|all"),
+        },
+        ("d" | "dis", _) => {
+            let addr = args.get(1).and_then(|a| parse_symbolic(a)).unwrap_or_else(get_pc);
+            let count = args.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+            disassemble_range(addr, count);
+        }
+        ("b" | "break", _) => match args.get(1) {
+            Some(name) if name.starts_with('_') => match traps::trap_by_name(name) {
+                Some(word) => {
+                    let n = push_breakpoint(Breakpoint::Trap(word));
+                    println!("Breakpoint {} on {}", n, traps::describe(word));
+                }
+                None => println!("Unknown trap '{}'", name),
+            },
+            Some(addr) => match parse_symbolic(addr) {
+                Some(addr) => println!("Breakpoint {} at {}", add_breakpoint(addr), format_address(addr)),
+                None => println!("usage: break ADDR|_TrapName"),
+            },
+            None => println!("usage: break ADDR|_TrapName"),
+        },
+        ("bd" | "delete", _) => {
+            let mut state = STATE.lock().unwrap();
+            match args.get(1).map(|n| n.parse::<usize>()) {
+                None => state.breakpoints.clear(),
+                Some(Ok(n)) if n >= 1 && n <= state.breakpoints.len() => {
+                    state.breakpoints.remove(n - 1);
+                }
+                Some(_) => println!("No breakpoint {}", args[1]),
+            }
+            state.update_attention();
+        }
+        ("bl" | "breaks", _) => {
+            for (i, bp) in STATE.lock().unwrap().breakpoints.iter().enumerate() {
+                println!("{:3}  {}", i + 1, bp.describe());
+            }
+        }
+        ("w" | "watch", _) => match args.get(1).and_then(|s| watchpoint::parse_spec(s)) {
+            Some((addr, len, kind)) => {
+                let n = watchpoint::add(addr, len, kind);
+                println!("Watchpoint {} on 0x{:06X}+{} for {}", n + 1, addr, len, kind);
+            }
+            None => println!("usage: watch [r|w|c]:ADDR[+LEN]"),
+        },
+        ("wd", _) => match args.get(1).map(|n| n.parse::<usize>()) {
+            None => watchpoint::clear(),
+            Some(Ok(n)) if n >= 1 && watchpoint::remove(n - 1).is_some() => {}
+            Some(_) => println!("No watchpoint {}", args[1]),
+        },
+        ("wl" | "watches", _) => {
+            for (i, wp) in watchpoint::list().iter().enumerate() {
+                println!("{:3}  0x{:06X}+{}  {}", i + 1, wp.start, wp.len, wp.kind);
+            }
+        }
+        ("traplog", _) => match args.get(1).copied() {
+            Some("on") => traps::set_logging(true),
+            Some("off") => traps::set_logging(false),
+            Some("reset") => traps::reset_counts(),
+            _ => println!("Trap logging is {}", if traps::logging() { "on" } else { "off" }),
+        },
+        ("traps", _) => {
+            let limit = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(20);
+            for (word, count) in traps::call_counts().into_iter().take(limit) {
+                println!("{:10}  {}", count, traps::describe(word));
+            }
+        }
+        ("fstrace", _) => match args.get(1).copied() {
+            Some("on") => calltrace::set_enabled(true),
+            Some("off") => calltrace::set_enabled(false),
+            _ => println!("File/Resource Manager tracing is {}", if calltrace::enabled() { "on" } else { "off" }),
+        },
+        ("segtrace", _) => match args.get(1).copied() {
+            Some("on") => segments::set_logging(true),
+            Some("off") => segments::set_logging(false),
+            _ => println!("Segment Loader tracing is {}", if segments::logging() { "on" } else { "off" }),
+        },
+        ("a5" | "a5world", _) => print!("{}", segments::a5_world()),
+        ("profile", _) => match (args.get(1).copied(), args.get(2)) {
+            (Some("start"), _) => profiler::start(),
+            (Some("stop"), _) => profiler::stop(),
+            (Some("reset"), _) => profiler::reset(),
+            (Some("save"), Some(path)) => match profiler::write_folded(path) {
+                Ok(()) => println!("Folded stacks written to {}", path),
+                Err(e) => println!("Failed to write {}: {}", path, e),
+            },
+            (Some("hot"), n) => {
+                let limit = n.and_then(|n| n.parse().ok()).unwrap_or(20);
+                let _ = profiler::write_hot_report(&mut io::stdout(), limit);
+            }
+            _ => println!("usage: profile start|stop|reset|save FILE|hot [N]"),
+        },
+        ("coverage", _) => match (args.get(1).copied(), args.get(2)) {
+            (Some("start"), _) => coverage::start(),
+            (Some("stop"), _) => coverage::stop(),
+            (Some("reset"), _) => coverage::reset(),
+            (Some("save"), Some(path)) => match coverage::write_addresses(path) {
+                Ok(()) => println!("Coverage written to {}", path),
+                Err(e) => println!("Failed to write {}: {}", path, e),
+            },
+            (Some("listing"), Some(path)) => match coverage::write_listing(path) {
+                Ok(()) => println!("Coverage listing written to {}", path),
+                Err(e) => println!("Failed to write {}: {}", path, e),
+            },
+            (Some("diff"), Some(path)) => match coverage::compare(path) {
+                Ok((new, missing)) => {
+                    println!("{} addresses only in this run, {} only in {}", new.len(), missing.len(), path);
+                    for location in new.iter().take(20) {
+                        println!("  + {}", location);
+                    }
+                    for location in missing.iter().take(20) {
+                        println!("  - {}", location);
+                    }
+                }
+                Err(e) => println!("Failed to read {}: {}", path, e),
+            },
+            _ => println!("usage: coverage start|stop|reset|save FILE|listing FILE|diff FILE"),
+        },
+        ("sym", _) => symbol_command(&args[1..]),
+        ("heap", _) => heap_command(&args[1..]),
+        ("res", _) => resource_command(&args[1..]),
+        ("windows" | "wins", _) => {
+            let list = windows::window_list();
+            if list.is_empty() {
+                println!("WindowList is empty");
+            }
+            for window in list {
+                println!("{}", windows::describe_window(window));
+            }
+        }
+        ("events", _) => {
+            let events = queues::elements(queues::EVENT_QUEUE);
+            println!("{} events queued", events.len());
+            for event in events {
+                println!("  {}", queues::describe_event(event));
+            }
+        }
+        ("vbl", _) => {
+            let tasks = queues::elements(queues::VBL_QUEUE);
+            println!("{} VBL tasks installed", tasks.len());
+            for task in tasks {
+                println!("  {}", queues::describe_vbl_task(task));
+            }
+        }
+        ("files", _) => {
+            let open = files::open_files();
+            println!("{} files open", open.len());
+            for (refnum, fcb) in open {
+                println!("  {}", files::describe_fcb(refnum, fcb));
+            }
+        }
+        ("vols" | "volumes", _) => {
+            for vcb in files::volumes() {
+                println!("  {}", files::describe_vcb(vcb));
+            }
+        }
+        ("drivers" | "units", _) => {
+            for (unit, dce) in drivers::units() {
+                println!("  {}", drivers::describe_unit(unit, dce));
+            }
+        }
+        ("patches", _) => {
+            let patches = patches::patched();
+            println!("{} traps patched", patches.len());
+            for patch in patches {
+                println!("  {}", patch.describe());
+            }
+        }
+        ("port", _) => {
+            let port = match args.get(1) {
+                Some(addr) => parse_symbolic(addr),
+                None => Some(windows::the_port()).filter(|&p| p != 0),
+            };
+            match port {
+                Some(port) => println!("{}", windows::describe_port(port)),
+                None => println!("No current port"),
+            }
+        }
+        ("lowmem" | "lm", _) => {
+            let globals = match args.get(1) {
+                Some(name) => lowmem::by_name(name).map(|g| vec![g]).unwrap_or_else(|| lowmem::matching(name)),
+                None => lowmem::GLOBALS.iter().collect(),
+            };
+            if globals.is_empty() {
+                println!("No low-memory global matches '{}'", args[1]);
+            }
+            for global in globals {
+                println!("  {:04X}  {:<14} {}", global.addr, global.name, lowmem::format_value(global));
+            }
+        }
+        ("macsbug", _) => {
+            let range = match (args.get(1).and_then(|a| parse_symbolic(a)), args.get(2).and_then(|a| parse_symbolic(a))) {
+                (Some(start), Some(end)) => Some((start, end)),
+                _ => None,
+            };
+            println!("{} MacsBug names added to the symbol table", macsbug::load_symbols(range));
+        }
+        ("report", _) => {
+            let report = crash::report("Requested from console");
+            match args.get(1) {
+                Some(path) => match std::fs::write(path, &report) {
+                    Ok(()) => println!("Report written to {}", path),
+                    Err(e) => println!("Failed to write {}: {}", path, e),
+                },
+                None => print!("{}", report),
+            }
+        }
+        ("watchdog", _) => match args.get(1).copied() {
+            Some("off") => watchdog::stop(),
+            Some(seconds) => match seconds.parse() {
+                Ok(seconds) => watchdog::start(seconds),
+                Err(_) => println!("usage: watchdog [SECONDS|off]"),
+            },
+            None => match watchdog::period() {
+                Some(seconds) => println!("Hang watchdog armed: {} emulated seconds", seconds),
+                None => println!("Hang watchdog is off"),
+            },
+        },
+        ("catch", _) => match args.get(1).copied() {
+            Some("on") => crash::set_break_on_exception(true),
+            Some("off") => crash::set_break_on_exception(false),
+            _ => println!("Stopping on exceptions is {}", if crash::break_on_exception() { "on" } else { "off" }),
+        },
+        ("bt" | "backtrace", _) => {
+            for (i, frame) in backtrace::backtrace().iter().enumerate() {
+                println!("#{:<3} {}", i, frame.describe());
+            }
+        }
+        ("via", _) => match VIA.lock().unwrap().as_ref() {
+            Some(via) => println!("{}", via.dump()),
+            None => println!("VIA not initialized"),
+        },
+        ("iwm", _) => println!("{}", iwm_state()),
+        ("s" | "step" | "n" | "next" | "c" | "continue" | "u" | "until", false) => {
+            println!("Machine is running; use 'stop' first");
+        }
+        (cmd, _) => println!("Unknown command '{}', try 'help'", cmd),
+    }
+    prompt();
+}
+
+fn print_help() {
+    println!("Execution (while stopped):");
+    println!("  s|step [N]         single-step N instructions");
+    println!("  n|next             step over JSR/BSR/TRAP/A-line traps");
+    println!("  c|continue         resume execution");
+    println!("  u|until ADDR       run to ADDR");
+    println!("  stop|pause         stop a running machine (or press F12)");
+    println!("Inspection:");
+    println!("  r|regs [REG VAL]   show registers, or set one (d0-d7, a0-a7, sp, pc, sr, usp, ssp)");
+    println!("  m|mem ADDR [LEN]   hex dump memory");
+    println!("  e|edit ADDR B...   write bytes to RAM");
+    println!("  d|dis [ADDR] [N]   disassemble N instructions");
+    println!("  heap [sys|app|ADDR] check the heap zones, or list one zone's blocks");
+    println!("  res [TYPE]         list the open resource maps (attrs: Sys heap, Purgeable, Locked, pRotected, preload, Changed)");
+    println!("  res dump TYPE ID FILE  write a loaded resource's data to a host file");
+    println!("  windows            list WindowList, frontmost first");
+    println!("  port [ADDR]        decode thePort, or the GrafPort at ADDR");
+    println!("  events             list the OS event queue");
+    println!("  vbl                list the VBL task queue");
+    println!("  files              list open files from the FCB buffer");
+    println!("  vols               list mounted volumes from the VCB queue");
+    println!("  drivers            list installed drivers from the unit table");
+    println!("  patches            list traps patched away from their ROM routines, and who owns the patch");
+    println!("  poke ADDR b|w|l VALUE  write a byte, word or long");
+    println!("  dump ram|rom FILE  write installed RAM or the ROM to a host file");
+    println!("  dump ADDR LEN FILE write a memory range to a host file");
+    println!("  load FILE ADDR [go]  copy a host file into RAM, optionally setting PC to ADDR");
+    println!("  search ...         find values or strings in RAM and narrow the hits ('search' for usage)");
+    println!("  freeze [ADDR b|w|l [VALUE]]  list freezes, or pin a value (default: current) every frame");
+    println!("  unfreeze N|all     remove a freeze");
+    println!("  shadow on|off|list report reads of RAM never written (or fresh from _NewPtr/_NewHandle)");
+    println!("  lm|lowmem [NAME]   show low-memory globals, decoded (NAME may be a partial match)");
+    println!("  bt|backtrace       walk A6 frames and trap dispatcher frames");
+    println!("  report [FILE]      diagnostic report: registers, recent instructions, backtrace");
+    println!("  watchdog [SECS|off]  stop when the PC stays in a small loop with interrupts masked and Ticks stalled");
+    println!("  catch [on|off]     stop on bus errors, illegal instructions and other error exceptions");
+    println!("  via, iwm           dump device state");
+    println!("Traps:");
+    println!("  traplog [on|off|reset]  log every A-line trap with its caller PC");
+    println!("  traps [N]          show the N most-called traps");
+    println!("  fstrace [on|off]   log File/Resource Manager calls with decoded arguments and results");
+    println!("  segtrace [on|off]  log _LoadSeg/_UnloadSeg and where segments are loaded");
+    println!("  a5                 show the A5 world and jump table entries");
+    println!("Profiling:");
+    println!("  profile start|stop|reset  control cycle profiling");
+    println!("  profile save FILE  write folded stacks for flamegraph tools");
+    println!("  profile hot [N]    show the N hottest PCs");
+    println!("  coverage start|stop|reset  control code coverage collection");
+    println!("  coverage save FILE      write executed addresses, one per line");
+    println!("  coverage listing FILE   write a ROM disassembly marking executed instructions");
+    println!("  coverage diff FILE      compare with a saved address list");
+    println!("Symbols (ADDR may be NAME or NAME+OFFSET wherever an address is expected):");
+    println!("  sym ADDR|NAME      show the symbol for an address, or the address of a symbol");
+    println!("  sym load FILE      load an 'ADDRESS NAME' symbol file");
+    println!("  sym add NAME ADDR  define a symbol");
+    println!("  sym base SEG ADDR  set the load address of a segment's relative symbols");
+    println!("  sym list [FILTER]  list symbols");
+    println!("  macsbug [START END]  add MacsBug names embedded in code (default: all RAM)");
+    println!("Breakpoints and watchpoints:");
+    println!("  b|break ADDR       set breakpoint");
+    println!("  b|break _TrapName  break on every call of an A-line trap");
+    println!("  bd|delete [N]      delete breakpoint N (all if omitted)");
+    println!("  bl|breaks          list breakpoints");
+    println!("  w|watch SPEC       watch [r|w|c]:ADDR[+LEN]; ADDR may name a low-memory global, e.g. c:Ticks");
+    println!("  wd [N]             delete watchpoint N (all if omitted)");
+    println!("  wl|watches         list watchpoints");
+    println!("Console:");
+    println!("  history, !!, !N    command history; an empty line repeats the last command");
+    println!("  q|quit             exit the emulator");
+}
+
+fn symbol_command(args: &[&str]) {
+    match args {
+        ["load", path] => match symbols::load(path) {
+            Ok(n) => println!("{} symbols loaded", n),
+            Err(e) => println!("{}", e),
+        },
+        ["add", name, addr] => match parse_address(addr) {
+            Some(addr) => symbols::add(addr, name),
+            None => println!("Bad address '{}'", addr),
+        },
+        ["base", segment, addr] => match parse_address(addr) {
+            Some(addr) => symbols::set_segment_base(segment, addr),
+            None => println!("Bad address '{}'", addr),
+        },
+        ["list"] | ["list", _] => {
+            for (addr, name) in symbols::list(args.get(1).copied().unwrap_or("")) {
+                println!("  0x{:06X}  {}", addr, name);
+            }
+        }
+        [query] => match parse_symbolic(query) {
+            Some(addr) => println!("{}", format_address(addr)),
+            None => println!("Unknown symbol '{}'", query),
+        },
+        _ => println!("usage: sym ADDR|NAME, sym load FILE, sym add NAME ADDR, sym base SEGMENT ADDR, sym list [FILTER]"),
+    }
+}
+
+fn heap_command(args: &[&str]) {
+    let zones = match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+        None => heap::zones(),
+        Some("sys") => vec![("SysZone", peek_u32(heap::SYS_ZONE) & 0xFFFFFF)],
+        Some("app") => vec![("ApplZone", peek_u32(heap::APPL_ZONE) & 0xFFFFFF)],
+        Some(addr) => match parse_symbolic(addr) {
+            Some(zone) => vec![("Zone", zone)],
+            None => {
+                println!("usage: heap [sys|app|ADDR]");
+                return;
+            }
+        },
+    };
+    for (name, addr) in zones {
+        let zone = heap::walk(addr);
+        println!("{} {}", name, zone.summary());
+        // A bare `heap` only checks the zones; naming one lists its blocks
+        if !args.is_empty() {
+            for block in &zone.blocks {
+                println!("  {}", block);
+            }
+        }
+        match heap::describe_error(&zone) {
+            Some(error) => println!("  {}", error),
+            None => println!("  OK"),
+        }
+    }
+}
+
+fn size_arg(s: &str) -> Option<u32> {
+    match s {
+        "b" => Some(1),
+        "w" => Some(2),
+        "l" => Some(4),
+        _ => None,
+    }
+}
+
+/// Parses a value that must fit in `size` bytes.
+fn sized_value(s: &str, size: u32) -> Option<u32> {
+    parse_address(s).filter(|&value| memsearch::fits(value, size))
+}
+
+fn search_command(args: &[&str]) {
+    let count = match args {
+        ["bytes", bytes @ ..] if !bytes.is_empty() => {
+            let pattern: Option<Vec<u8>> = bytes.iter().map(|b| parse_address(b).filter(|&v| v <= 0xFF).map(|v| v as u8)).collect();
+            match pattern {
+                Some(pattern) => memsearch::search_bytes(&pattern),
+                None => {
+                    println!("usage: search bytes BYTE...");
+                    return;
+                }
+            }
+        }
+        ["pstr" | "cstr", words @ ..] if !words.is_empty() => {
+            let text = words.join(" ");
+            let mut pattern = text.into_bytes();
+            if args[0] == "pstr" {
+                if pattern.len() > 255 {
+                    println!("A Pascal string holds at most 255 bytes");
+                    return;
+                }
+                pattern.insert(0, pattern.len() as u8);
+            } else {
+                pattern.push(0);
+            }
+            memsearch::search_bytes(&pattern)
+        }
+        ["all", size] => match size_arg(size) {
+            Some(size) => memsearch::search_all(size),
+            None => {
+                println!("usage: search all b|w|l");
+                return;
+            }
+        },
+        ["changed"] => memsearch::narrow(memsearch::Filter::Changed),
+        ["unchanged"] => memsearch::narrow(memsearch::Filter::Unchanged),
+        ["increased"] => memsearch::narrow(memsearch::Filter::Increased),
+        ["decreased"] => memsearch::narrow(memsearch::Filter::Decreased),
+        ["=", value] => match parse_address(value) {
+            Some(value) => memsearch::narrow(memsearch::Filter::Equals(value)),
+            None => {
+                println!("Bad value '{}'", value);
+                return;
+            }
+        },
+        ["list"] | ["list", _] => {
+            let limit = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(20);
+            for c in memsearch::candidates().iter().take(limit) {
+                println!("  {}  = 0x{:0w$X}", format_address(c.addr), c.value, w = c.size as usize * 2);
+            }
+            return;
+        }
+        [size, value] => match size_arg(size).and_then(|size| Some((size, sized_value(value, size)?))) {
+            Some((size, value)) => memsearch::search_value(value, size),
+            _ => {
+                print_search_usage();
+                return;
+            }
+        },
+        _ => {
+            print_search_usage();
+            return;
+        }
+    };
+    println!("{} candidates", count);
+}
+
+fn print_search_usage() {
+    println!("usage: search b|w|l VALUE, search bytes BYTE..., search pstr|cstr TEXT, search all b|w|l");
+    println!("       search changed|unchanged|increased|decreased, search = VALUE, search list [N]");
+}
+
+fn resource_command(args: &[&str]) {
+    match args {
+        [] | [_] => {
+            let filter = args.first().and_then(|t| resources::parse_type(t));
+            for map in resources::maps() {
+                println!("Map handle 0x{:06X}  refNum {}  attrs ${:04X}  {} resources", map.handle, map.refnum, map.attrs, map.resources.len());
+                for resource in map.resources.iter().filter(|r| filter.is_none_or(|t| r.rtype == t)) {
+                    println!("  {}", resource);
+                }
+            }
+        }
+        ["dump", rtype, id, path] => match (resources::parse_type(rtype), id.parse::<i16>()) {
+            (Some(rtype), Ok(id)) => match resources::dump(rtype, id, path) {
+                Ok(len) => println!("{} bytes written to {}", len, path),
+                Err(e) => println!("{}", e),
+            },
+            _ => println!("usage: res dump TYPE ID FILE"),
+        },
+        _ => println!("usage: res [TYPE], res dump TYPE ID FILE"),
+    }
+}
+
+fn set_temp_break(addr: u32) {
+    let mut state = STATE.lock().unwrap();
+    state.temp_break = Some(addr);
+    state.update_attention();
+}
+
+fn step_instructions(count: u32) {
+    for _ in 0..count {
+        execute_instruction();
+        if let Some(reason) = take_pending() {
+            println!("{}", reason);
+            break;
+        }
+    }
+    show_location();
+}
+
+fn step_over() {
+    let pc = get_pc();
+    if is_call_instruction(peek_u16(pc)) {
+        let (_, len) = disassemble(pc);
+        set_temp_break(pc.wrapping_add(len));
+        resume();
+    } else {
+        step_instructions(1);
+    }
+}
+
+/// Steps off the current instruction (so a breakpoint here doesn't refire) and lets the CPU run.
+/// Returns false if that first instruction already stopped the machine again.
+pub(crate) fn resume() -> bool {
+    execute_instruction();
+    if let Some(reason) = take_pending() {
+        println!("{}", reason);
+        show_location();
+        return false;
+    }
+    let mut state = STATE.lock().unwrap();
+    if state.temp_break == Some(get_pc()) {
+        state.temp_break = None;
+        state.update_attention();
+        drop(state);
+        show_location();
+        return false;
+    }
+    state.paused = false;
+    true
+}
+
+fn registers(args: &[&str]) {
+    match args {
+        [] => display_registers(),
+        [name] => match reg_by_name(name) {
+            Some(reg) => println!("{} = 0x{:08X}", name.to_ascii_uppercase(), get_reg(reg)),
+            None => println!("Unknown register '{}'", name),
+        },
+        [name, value, ..] => match (reg_by_name(name), parse_symbolic(value)) {
+            (Some(reg), Some(value)) => set_reg(reg, value),
+            (None, _) => println!("Unknown register '{}'", name),
+            (_, None) => println!("Bad value '{}'", value),
+        },
+    }
+}
+
+fn dump_memory(addr: u32, len: u32) {
+    for line in (0..len).step_by(16) {
+        let base = addr.wrapping_add(line);
+        let bytes: Vec<u8> = (0..16.min(len - line)).map(|i| peek_u8(base.wrapping_add(i))).collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes.iter()
+            .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+            .collect();
+        println!("{:06X}  {:<47}  {}", base, hex.join(" "), ascii);
+    }
+}
+
+fn edit_memory(args: &[&str]) {
+    let Some(addr) = args.first().and_then(|a| parse_symbolic(a)) else {
+        println!("usage: edit ADDR BYTE...");
+        return;
+    };
+    for (i, byte) in args[1..].iter().enumerate() {
+        let value = match parse_address(byte) {
+            Some(v) if v <= 0xFF => v as u8,
+            _ => {
+                println!("Bad byte '{}'", byte);
+                return;
+            }
+        };
+        let target = addr.wrapping_add(i as u32);
+        if !poke_u8(target, value) {
+            println!("0x{:06X} is not RAM", target);
+            return;
+        }
+    }
+}
+
+fn disassemble_range(addr: u32, count: u32) {
+    let mut pc = addr;
+    for _ in 0..count {
+        let (text, len) = disassemble(pc);
+        if let Some(name) = symbols::exact(pc) {
+            println!("{}:", name);
+        }
+        println!("  0x{:06X}  {}", pc, text);
+        pc = pc.wrapping_add(len.max(2));
+    }
+}
@@ -0,0 +1,348 @@
+/* Static ROM disassembler.
+ *
+ * Recursive descent from a set of entry points read from the ROM image (the
+ * reset vector, and every routine in the ROM's own copy of the trap
+ * dispatch table, found by its shape), or from the exception vectors and
+ * dispatch table in RAM once the ROM has installed them: every branch, call
+ * and jump with a computable
+ * target is followed, and everything never reached as code is listed as
+ * data.  Instructions come from the same Musashi disassembler the debugger
+ * uses, so low-memory globals are named, and branch targets become labels.
+ */
+
+use crate::cpu::disassemble;
+use crate::memory::{peek_u16, peek_u32, peek_u8, ROM_BASE, ROM_SIZE};
+use crate::patches::{entry_address, trap_word, TRAP_ENTRIES, TRAP_TABLE};
+use crate::symbols::{self, rom_checksum};
+use crate::traps;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const DATA_BYTES_PER_LINE: u32 = 8;
+// The ROM's copy of the dispatch table names at least this many routines...
+const MIN_TABLE_ROUTINES: usize = 128;
+// ...and sends at least this many traps to the one "unimplemented" routine
+const MIN_UNIMPLEMENTED: usize = 8;
+
+pub struct Listing {
+    starts: BTreeSet<u32>,
+    code: Vec<bool>,
+    labels: BTreeMap<u32, Vec<String>>,
+    targets: BTreeMap<u32, Vec<u32>>,
+}
+
+/// Maps an address seen by the CPU (ROM at 0 during reset, or a mirror) to its ROM_BASE address.
+fn rom_address(addr: u32) -> Option<u32> {
+    let addr = addr & 0xFFFFFF;
+    if addr < ROM_SIZE as u32 {
+        Some(ROM_BASE + addr)
+    } else if addr & 0xF00000 == ROM_BASE {
+        Some(ROM_BASE + (addr & (ROM_SIZE as u32 - 1)))
+    } else {
+        None
+    }
+}
+
+fn vector_label(vector: u32) -> String {
+    match vector {
+        2 => "BusError".to_string(),
+        3 => "AddressError".to_string(),
+        4 => "IllegalInstruction".to_string(),
+        5 => "ZeroDivide".to_string(),
+        6 => "ChkTrap".to_string(),
+        7 => "TrapV".to_string(),
+        8 => "PrivilegeViolation".to_string(),
+        9 => "Trace".to_string(),
+        10 => "ATrapDispatcher".to_string(),
+        11 => "FLineTrap".to_string(),
+        24 => "SpuriousInterrupt".to_string(),
+        25..=31 => format!("Level{}Interrupt", vector - 24),
+        32..=47 => format!("Trap{}", vector - 32),
+        _ => format!("Vector{}", vector),
+    }
+}
+
+/// The reset vector from the ROM header.
+pub fn reset_entry() -> Vec<(u32, String)> {
+    rom_address(peek_u32(ROM_BASE + 4)).map(|addr| (addr, "Reset".to_string())).into_iter().collect()
+}
+
+/// Names the ROM routines in a dispatch table, given its entries in trap number order.
+fn table_entries(table: impl Iterator<Item = u16>) -> Vec<(u32, String)> {
+    // Entries are (address - ROMBase) / 2; bit 15 marks patches in RAM, which are skipped
+    table.zip(0..TRAP_ENTRIES)
+        .filter(|&(entry, _)| entry != 0 && entry & 0x8000 == 0)
+        .map(|(entry, index)| {
+            let word = trap_word(index);
+            let name = traps::trap_name(word).map(str::to_string).unwrap_or_else(|| format!("_Trap{:04X}", word));
+            (entry_address(entry), name)
+        })
+        .collect()
+}
+
+/// Finds the ROM's copy of the trap dispatch table in `image`: 512 entries in the RAM
+/// table's format, all pointing at code outside the table, naming many routines and
+/// sending many traps to one.  Returns the offset of the candidate naming the most routines,
+/// then filling the most entries.
+fn find_trap_table(image: &[u8]) -> Option<usize> {
+    let word = |offset: usize| u16::from_be_bytes([image[offset], image[offset + 1]]);
+    let len = TRAP_ENTRIES as usize * 2;
+    let mut best: Option<(usize, (usize, usize))> = None;
+    for start in (0..image.len().saturating_sub(len - 1)).step_by(2) {
+        let mut counts: BTreeMap<u16, usize> = BTreeMap::new();
+        let fits = (start..start + len).step_by(2).map(word).all(|entry| {
+            let target = entry as usize * 2;
+            if entry != 0 {
+                *counts.entry(entry).or_insert(0) += 1;
+            }
+            entry == 0 || (entry & 0x8000 == 0 && !(start..start + len).contains(&target)
+                && target + 2 <= image.len() && !matches!(word(target), 0x0000 | 0xFFFF))
+        });
+        let routines = counts.len();
+        let unimplemented = counts.values().copied().max().unwrap_or(0);
+        let score = (routines, counts.values().sum::<usize>());
+        if fits && routines >= MIN_TABLE_ROUTINES && unimplemented >= MIN_UNIMPLEMENTED
+            && best.is_none_or(|(_, most)| score > most)
+        {
+            best = Some((start, score));
+        }
+    }
+    best.map(|(start, _)| start)
+}
+
+/// The reset vector and the routines in the ROM's dispatch table, read from the ROM image.
+pub fn image_entries() -> Vec<(u32, String)> {
+    let mut entries = reset_entry();
+    let image: Vec<u8> = (0..ROM_SIZE as u32).map(|i| peek_u8(ROM_BASE + i)).collect();
+    match find_trap_table(&image) {
+        Some(offset) => {
+            info!("Trap dispatch table found in the ROM at ${:06X}", ROM_BASE + offset as u32);
+            let table = (0..TRAP_ENTRIES as usize).map(|i| u16::from_be_bytes([image[offset + i * 2], image[offset + i * 2 + 1]]));
+            entries.extend(table_entries(table));
+        }
+        None => warn!("No trap dispatch table found in the ROM image"),
+    }
+    entries
+}
+
+/// Exception vectors and trap dispatch table entries currently in RAM that point into ROM.
+/// Only meaningful after the ROM has initialised them.
+pub fn installed_entries() -> Vec<(u32, String)> {
+    let mut entries = Vec::new();
+    for vector in 2..64 {
+        let handler = peek_u32(vector * 4);
+        if let Some(addr) = rom_address(handler).filter(|_| handler != 0) {
+            entries.push((addr, vector_label(vector)));
+        }
+    }
+    entries.extend(table_entries((0..TRAP_ENTRIES).map(|index| peek_u16(TRAP_TABLE + index * 2))));
+    entries
+}
+
+/// Where control can go after the instruction at `addr`: ((target, is call) pairs, whether it falls through).
+fn flow(addr: u32) -> (Vec<(u32, bool)>, bool) {
+    let opcode = peek_u16(addr);
+    let ext = peek_u16(addr + 2);
+    let relative = |disp: i32| addr.wrapping_add(2).wrapping_add(disp as u32);
+    let ea_target = || match (opcode >> 3) & 7 {
+        7 => match opcode & 7 {
+            0 => Some(ext as i16 as i32 as u32),
+            1 => Some(peek_u32(addr + 2)),
+            2 => Some(relative(ext as i16 as i32)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match opcode {
+        0x4E73 | 0x4E74 | 0x4E75 | 0x4E77 | 0x4AFC => (vec![], false),
+        _ if opcode & 0xFFC0 == 0x4EC0 => (ea_target().map(|t| (t, false)).into_iter().collect(), false),
+        _ if opcode & 0xFFC0 == 0x4E80 => (ea_target().map(|t| (t, true)).into_iter().collect(), true),
+        _ if opcode & 0xF000 == 0x6000 => {
+            let disp8 = opcode as u8 as i8 as i32;
+            let target = if disp8 == 0 { relative(ext as i16 as i32) } else { relative(disp8) };
+            match (opcode >> 8) & 0xF {
+                0 => (vec![(target, false)], false),
+                1 => (vec![(target, true)], true),
+                _ => (vec![(target, false)], true),
+            }
+        }
+        _ if opcode & 0xF0F8 == 0x50C8 => (vec![(relative(ext as i16 as i32), false)], true),
+        // Auto-pop Toolbox traps return straight to the caller's caller
+        _ if traps::is_toolbox(opcode) && opcode & 0x0400 != 0 => (vec![], false),
+        _ => (vec![], true),
+    }
+}
+
+/// Follows code from `entries` through the ROM.
+pub fn trace(entries: &[(u32, String)]) -> Listing {
+    let mut listing = Listing {
+        starts: BTreeSet::new(),
+        code: vec![false; ROM_SIZE],
+        labels: BTreeMap::new(),
+        targets: BTreeMap::new(),
+    };
+    let mut work: Vec<u32> = Vec::new();
+    for (addr, name) in entries {
+        listing.add_label(*addr, name);
+        work.push(*addr);
+    }
+    while let Some(start) = work.pop() {
+        let mut addr = start;
+        loop {
+            let offset = (addr - ROM_BASE) as usize;
+            if addr & 1 != 0 || listing.starts.contains(&addr) {
+                break;
+            }
+            let (text, len) = disassemble(addr);
+            let end = offset + len.max(2) as usize;
+            if (text.starts_with("dc.w") && !traps::is_trap(peek_u16(addr))) || end > ROM_SIZE || listing.code[offset..end].iter().any(|&c| c) {
+                break;
+            }
+            listing.starts.insert(addr);
+            listing.code[offset..end].iter_mut().for_each(|c| *c = true);
+            let (targets, falls_through) = flow(addr);
+            for (target, call) in targets {
+                if let Some(target) = rom_address(target) {
+                    let prefix = if call { "sub" } else { "loc" };
+                    listing.add_label(target, &format!("{}_{:06X}", prefix, target));
+                    listing.targets.entry(addr).or_default().push(target);
+                    work.push(target);
+                }
+            }
+            if !falls_through {
+                break;
+            }
+            addr += len.max(2);
+            if addr >= ROM_BASE + ROM_SIZE as u32 {
+                break;
+            }
+        }
+    }
+    listing
+}
+
+impl Listing {
+    fn add_label(&mut self, addr: u32, name: &str) {
+        let names = self.labels.entry(addr).or_default();
+        // Generated sub_/loc_ labels only fill in for a missing real name
+        let generated = |n: &str| n.starts_with("sub_") || n.starts_with("loc_");
+        if generated(name) && !names.is_empty() {
+            return;
+        }
+        names.retain(|n| !generated(n));
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    fn label(&self, addr: u32) -> Option<String> {
+        symbols::exact(addr).or_else(|| self.labels.get(&addr).and_then(|names| names.first().cloned()))
+    }
+
+    pub fn code_bytes(&self) -> usize {
+        self.code.iter().filter(|&&c| c).count()
+    }
+
+    /// Writes the whole ROM as a listing.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "; ROM checksum {:08X}, {} bytes at ${:06X}", rom_checksum(), ROM_SIZE, ROM_BASE)?;
+        writeln!(out, "; {} instructions, {} of {} bytes code", self.starts.len(), self.code_bytes(), ROM_SIZE)?;
+        let mut addr = ROM_BASE;
+        let end = ROM_BASE + ROM_SIZE as u32;
+        while addr < end {
+            if self.labels.contains_key(&addr) || symbols::exact(addr).is_some() {
+                writeln!(out)?;
+                if let Some(name) = symbols::exact(addr) {
+                    writeln!(out, "{}:", name)?;
+                }
+                for name in self.labels.get(&addr).into_iter().flatten() {
+                    writeln!(out, "{}:", name)?;
+                }
+            }
+            if self.starts.contains(&addr) {
+                addr += self.write_instruction(&mut out, addr)?;
+            } else {
+                addr += self.write_data(&mut out, addr, end)?;
+            }
+        }
+        out.flush()
+    }
+
+    fn write_instruction(&self, out: &mut dyn Write, addr: u32) -> io::Result<u32> {
+        let (mut text, len) = disassemble(addr);
+        for &target in self.targets.get(&addr).into_iter().flatten() {
+            if let Some(label) = self.label(target) {
+                text = text.replace(&format!("${:x}", target), &label);
+            }
+        }
+        let words: Vec<String> = (0..len.max(2)).step_by(2).map(|i| format!("{:04X}", peek_u16(addr + i))).collect();
+        writeln!(out, "{:06X}  {:<24}  {}", addr, words.join(" "), text)?;
+        Ok(len.max(2))
+    }
+
+    fn write_data(&self, out: &mut dyn Write, start: u32, end: u32) -> io::Result<u32> {
+        let mut len = 0;
+        while start + len < end && len < DATA_BYTES_PER_LINE {
+            let addr = start + len;
+            if len > 0 && (self.starts.contains(&addr) || self.labels.contains_key(&addr)) {
+                break;
+            }
+            len += 1;
+        }
+        let bytes: Vec<u8> = (0..len).map(|i| peek_u8(start + i)).collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+        let ascii: String = bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+        writeln!(out, "{:06X}  {:<24}  dc.b    {:<32} ; {}", start, "", hex.join(","), ascii)?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(image: &mut [u8], offset: usize, word: u16) {
+        image[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    // 200 routines at $1000.., the other traps all at $3000, each starting with RTS
+    fn image_with_table(at: usize) -> Vec<u8> {
+        let mut image = vec![0; ROM_SIZE];
+        for index in 0..TRAP_ENTRIES as usize {
+            let target = if index < 200 { 0x1000 + index * 8 } else { 0x3000 };
+            put(&mut image, target, 0x4E75);
+            put(&mut image, at + index * 2, (target / 2) as u16);
+        }
+        image
+    }
+
+    #[test]
+    fn finds_the_dispatch_table_in_the_image() {
+        assert_eq!(find_trap_table(&image_with_table(0x2000)), Some(0x2000));
+        assert_eq!(find_trap_table(&vec![0; ROM_SIZE]), None);
+    }
+
+    #[test]
+    fn rejects_tables_pointing_at_nothing() {
+        let mut image = image_with_table(0x2000);
+        put(&mut image, 0x3000, 0);
+        assert_eq!(find_trap_table(&image), None);
+    }
+
+    #[test]
+    fn names_table_entries_by_trap() {
+        let entries = table_entries([0x0800, 0, 0x8100, 0x0900].into_iter());
+        assert_eq!(entries, [(0x401000, "_Open".to_string()), (0x401200, "_Write".to_string())]);
+    }
+
+    #[test]
+    fn maps_rom_addresses() {
+        assert_eq!(rom_address(0x2A), Some(0x40002A));
+        assert_eq!(rom_address(0x41002A), Some(0x40002A));
+        assert_eq!(rom_address(0x80412340), Some(0x402340));
+        assert_eq!(rom_address(0x600000), None);
+    }
+}
@@ -0,0 +1,96 @@
+/* Device Manager unit table.
+ *
+ * UTableBase points at UnitNtryCnt longs, each a handle to a device control
+ * entry or 0; unit n has reference number -(n+1).  The DCE points at its
+ * driver, through a handle when the driver is RAM-based, and the driver
+ * header ends with the driver's name.
+ */
+
+use crate::memory::{peek_pstring, peek_u16, peek_u32};
+use crate::symbols::format_address;
+
+const UTABLE_BASE: u32 = 0x11C;
+const UNIT_NTRY_CNT: u32 = 0x1D2;
+// DCE offsets
+const DCTL_DRIVER: u32 = 0;
+const DCTL_FLAGS: u32 = 4;
+const DCTL_QHEAD: u32 = 8;
+const DCTL_POSITION: u32 = 16;
+const DCTL_STORAGE: u32 = 20;
+const DCTL_REFNUM: u32 = 24;
+// Low byte of dCtlFlags
+const DRIVER_OPEN: u16 = 0x20;
+const RAM_BASED: u16 = 0x40;
+const DRIVER_ACTIVE: u16 = 0x80;
+// Offset of drvrName in the driver header
+const DRVR_NAME: u32 = 18;
+
+/// Installed units as (unit number, DCE address).
+pub fn units() -> Vec<(u16, u32)> {
+    let table = peek_u32(UTABLE_BASE) & 0xFFFFFF;
+    if table == 0 {
+        return Vec::new();
+    }
+    (0..peek_u16(UNIT_NTRY_CNT))
+        .filter_map(|unit| {
+            let handle = peek_u32(table + unit as u32 * 4) & 0xFFFFFF;
+            let dce = if handle == 0 { 0 } else { peek_u32(handle) & 0xFFFFFF };
+            (dce != 0).then_some((unit, dce))
+        })
+        .collect()
+}
+
+/// The driver's code, following the handle for RAM-based drivers.
+pub fn driver(dce: u32) -> u32 {
+    let driver = peek_u32(dce + DCTL_DRIVER) & 0xFFFFFF;
+    if peek_u16(dce + DCTL_FLAGS) & RAM_BASED != 0 && driver != 0 {
+        peek_u32(driver) & 0xFFFFFF
+    } else {
+        driver
+    }
+}
+
+/// Driver name and address, flags, queue state and storage of a unit.
+pub fn describe_unit(unit: u16, dce: u32) -> String {
+    let flags = peek_u16(dce + DCTL_FLAGS);
+    let code = driver(dce);
+    let name = if code == 0 { "?".to_string() } else { peek_pstring(code + DRVR_NAME) };
+    let state: Vec<&str> = [(DRIVER_OPEN, "open"), (RAM_BASED, "RAM"), (DRIVER_ACTIVE, "active")]
+        .iter()
+        .filter(|&&(bit, _)| flags & bit != 0)
+        .map(|&(_, label)| label)
+        .collect();
+    let head = peek_u32(dce + DCTL_QHEAD) & 0xFFFFFF;
+    let queue = if head == 0 { "idle".to_string() } else { format!("request 0x{:06X} queued", head) };
+    format!(
+        "unit {:2} refNum {:3}  {:<10} DCE 0x{:06X}  driver {}  flags ${:04X} {}  {}  position {}  storage 0x{:06X}",
+        unit, peek_u16(dce + DCTL_REFNUM) as i16, name, dce, format_address(code), flags, state.join(" "),
+        queue, peek_u32(dce + DCTL_POSITION), peek_u32(dce + DCTL_STORAGE) & 0xFFFFFF
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn describe_ram_based_unit() {
+        let dce = 0x37000;
+        put(dce, b"\x00\x03\x70\x80\x00\x60\x00\x00\x00\x03\x72\x00");
+        put(dce + DCTL_POSITION, b"\x00\x00\x02\x00\x00\x03\x73\x00\xFF\xFC");
+        // Driver handle and header
+        put(0x37080, b"\x00\x03\x71\x00");
+        put(0x37100 + DRVR_NAME, b"\x05.Sony");
+        assert_eq!(driver(dce), 0x37100);
+        let text = describe_unit(3, dce);
+        assert!(text.starts_with("unit  3 refNum  -4  .Sony      DCE 0x037000  driver 0x037100"), "{}", text);
+        assert!(text.ends_with("flags $0060 open RAM  request 0x037200 queued  position 512  storage 0x037300"), "{}", text);
+    }
+}
@@ -0,0 +1,192 @@
+/* Machine events for automation.
+ *
+ * Guest crashes are reported as `MachineEvent`s: System Errors (the bomb
+ * box, raised through _SysError, which stores its code in DSErrCode), the
+ * ROM's Sad Mac diagnostic, and the emulator's own crash and hang
+ * detection.  The Sad Mac routine is watched for at a given address or
+ * symbol; without one, ROM code parking the CPU for good (branching to
+ * itself with interrupts masked, or stopping at level 7) counts as one.  Events are queued for `take_events` and passed to any
+ * registered handlers; in headless mode the first one ends the run with a
+ * nonzero exit status.
+ */
+
+use crate::cpu::{dreg, get_sr};
+use crate::memory::{peek_u16, rom_offset};
+use crate::symbols::{self, format_address};
+use crate::traps;
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+lazy_static! {
+    static ref EVENTS: Mutex<Vec<MachineEvent>> = Mutex::new(Vec::new());
+    static ref HANDLERS: Mutex<Vec<Handler>> = Mutex::new(Vec::new());
+    // PC of the _SysError call in flight, reported with the DSErrCode it stores
+    static ref SYS_ERROR_CALLER: Mutex<Option<u32>> = Mutex::new(None);
+}
+
+type Handler = Box<dyn Fn(&MachineEvent) + Send>;
+
+pub const SYS_ERROR_TRAP: u16 = 0xA9C9;
+// Low-memory global where SysError leaves its error code
+pub const DS_ERR_CODE: u32 = 0xAF0;
+// Symbols tried, in order, for the ROM's Sad Mac routine
+const SAD_MAC_SYMBOLS: [&str; 2] = ["SadMac", "CritErr"];
+// DSErrCode values that aren't errors: the shutdown/restart alert
+const NOT_ERRORS: [i16; 1] = [40];
+const BRA_SELF: u16 = 0x60FE;
+const STOP: u16 = 0x4E72;
+const IPL_MASK: u16 = 0x0700;
+
+// Address of the Sad Mac routine; 0 when unknown
+static SAD_MAC_ADDR: AtomicU32 = AtomicU32::new(0);
+// Where the ROM last parked the CPU, so a halt loop is reported once
+static PARKED_AT: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MachineEvent {
+    /// _SysError stored an error code in DSErrCode; `pc` is its caller.
+    SystemError { code: i16, pc: u32 },
+    /// The ROM reached its Sad Mac routine; D7 holds the class code in its
+    /// high word and the subcode in its low word, D6 any extra detail.
+    SadMac { class: u16, subcode: u16, d6: u32 },
+    /// The emulator's own crash detection fired (halt, double fault, runaway).
+    Crash { reason: String },
+    /// The hang watchdog saw the PC stuck in `min_pc..=max_pc`.
+    Hang { min_pc: u32, max_pc: u32 },
+}
+
+impl MachineEvent {
+    /// Exit status used for this event in headless mode.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MachineEvent::SystemError { .. } => 2,
+            MachineEvent::SadMac { .. } => 3,
+            MachineEvent::Crash { .. } => 4,
+            MachineEvent::Hang { .. } => 5,
+        }
+    }
+}
+
+impl fmt::Display for MachineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineEvent::SystemError { code, pc } => {
+                write!(f, "System Error {} ({}) at {}", code, system_error_name(*code), format_address(*pc))
+            }
+            MachineEvent::SadMac { class, subcode, d6 } => {
+                write!(f, "Sad Mac {:02X}{:04X} (D6 ${:08X})", class, subcode, d6)
+            }
+            MachineEvent::Crash { reason } => write!(f, "Emulator crash: {}", reason),
+            MachineEvent::Hang { min_pc, max_pc } => {
+                write!(f, "Guest hung in {}..0x{:06X}", format_address(*min_pc), max_pc)
+            }
+        }
+    }
+}
+
+/// Names the standard System Error (DSErrCode) IDs.
+pub fn system_error_name(code: i16) -> &'static str {
+    match code {
+        1 => "bus error",
+        2 => "address error",
+        3 => "illegal instruction",
+        4 => "zero divide",
+        5 => "check trap",
+        6 => "overflow trap",
+        7 => "privilege violation",
+        8 => "trace trap",
+        9 => "line 1010 trap",
+        10 => "line 1111 trap",
+        11 => "miscellaneous hardware exception",
+        12 => "unimplemented core routine",
+        13 => "uninstalled interrupt",
+        14 => "I/O core error",
+        15 => "segment loader error",
+        16 => "floating point error",
+        17..=24 => "package not present",
+        25 => "out of memory",
+        26 => "bad program launch",
+        27 => "file system map trashed",
+        28 => "stack ran into heap",
+        30 => "disk insertion error",
+        31 => "wrong disk inserted",
+        33 => "negative ZcbFree",
+        40 => "shutdown/restart",
+        41 => "Finder not found",
+        84 => "menu purged",
+        _ => "?",
+    }
+}
+
+/// Registers a callback run for every event as it happens.
+pub fn add_handler(handler: impl Fn(&MachineEvent) + Send + 'static) {
+    HANDLERS.lock().unwrap().push(Box::new(handler));
+}
+
+/// Events raised since the last call.
+pub fn take_events() -> Vec<MachineEvent> {
+    std::mem::take(&mut *EVENTS.lock().unwrap())
+}
+
+pub fn emit(event: MachineEvent) {
+    error!("{}", event);
+    for handler in HANDLERS.lock().unwrap().iter() {
+        handler(&event);
+    }
+    EVENTS.lock().unwrap().push(event);
+}
+
+/// Looks up the Sad Mac routine in the symbol table unless an address was given.
+pub fn init(sad_mac: Option<u32>) {
+    let addr = sad_mac.or_else(|| SAD_MAC_SYMBOLS.iter().find_map(|name| symbols::resolve(name)));
+    if let Some(addr) = addr {
+        info!("Watching for Sad Mac at {}", format_address(addr));
+        SAD_MAC_ADDR.store(addr, Ordering::Relaxed);
+    } else {
+        info!("No Sad Mac routine known; watching for the ROM halting the CPU");
+    }
+}
+
+/// Whether ROM code at `pc` can never continue: a branch to itself with interrupts
+/// masked, or a STOP at level 7.
+fn parked(pc: u32, opcode: u16) -> bool {
+    let masked = |sr: u16| sr & IPL_MASK == IPL_MASK;
+    rom_offset(pc).is_some() && match opcode {
+        BRA_SELF => masked(get_sr() as u16),
+        STOP => masked(peek_u16(pc.wrapping_add(2))),
+        _ => false,
+    }
+}
+
+/// Called with each instruction before it executes.
+pub fn before_instruction(pc: u32, opcode: u16) {
+    if traps::is_trap(opcode) && traps::canonical(opcode) == SYS_ERROR_TRAP {
+        info!("_SysError called from {} with D0={}", format_address(pc), dreg(0) as i16);
+        *SYS_ERROR_CALLER.lock().unwrap() = Some(pc);
+    }
+    let sad_mac = SAD_MAC_ADDR.load(Ordering::Relaxed);
+    let hit = if sad_mac != 0 {
+        pc == sad_mac
+    } else {
+        (opcode == BRA_SELF || opcode == STOP) && SYS_ERROR_CALLER.lock().unwrap().is_none()
+            && parked(pc, opcode) && PARKED_AT.swap(pc, Ordering::Relaxed) != pc
+    };
+    if hit {
+        let d7 = dreg(7);
+        emit(MachineEvent::SadMac { class: (d7 >> 16) as u16, subcode: d7 as u16, d6: dreg(6) });
+    }
+}
+
+/// Called for guest writes to DSErrCode; only the code stored by a _SysError call counts.
+pub fn on_ds_err_code(code: i16) {
+    if code == 0 {
+        return;
+    }
+    let caller = SYS_ERROR_CALLER.lock().unwrap().take();
+    if let Some(pc) = caller.filter(|_| !NOT_ERRORS.contains(&code)) {
+        emit(MachineEvent::SystemError { code, pc });
+    }
+}
@@ -0,0 +1,125 @@
+/* File Manager state.
+ *
+ * FCBSPtr points at the FCB buffer: a length word followed by the file
+ * control blocks, each identified by its offset in the buffer (the file's
+ * reference number).  FSFCBLen gives the FCB size under HFS and is -1 under
+ * the 64K ROM's MFS, whose 30-byte FCBs carry no file name.  Mounted
+ * volumes are VCBs in the VCB queue; MFS and HFS share the offsets used here.
+ */
+
+use crate::memory::{peek_pstring, peek_u16, peek_u32, peek_u8};
+use crate::queues;
+
+const FCBS_PTR: u32 = 0x34E;
+const FS_FCB_LEN: u32 = 0x3F6;
+pub const VCB_QUEUE: u32 = 0x356;
+const MFS_FCB_LEN: u32 = 30;
+// FCB offsets
+const FCB_FL_NUM: u32 = 0;
+const FCB_MD_R_BYT: u32 = 4;
+const FCB_EOF: u32 = 8;
+const FCB_P_LEN: u32 = 12;
+const FCB_CR_PS: u32 = 16;
+const FCB_V_PTR: u32 = 20;
+const FCB_C_NAME: u32 = 62;
+// VCB offsets
+const VCB_ATRB: u32 = 18;
+const VCB_NM_FLS: u32 = 20;
+const VCB_NM_BLKS: u32 = 26;
+const VCB_AL_BLK_SIZ: u32 = 28;
+const VCB_FREE_BKS: u32 = 42;
+const VCB_VN: u32 = 44;
+const VCB_DRV_NUM: u32 = 72;
+const VCB_D_REF_NUM: u32 = 74;
+const VCB_V_REF_NUM: u32 = 78;
+
+fn fcb_len() -> u32 {
+    match peek_u16(FS_FCB_LEN) as i16 {
+        len if len > 0 => len as u32,
+        _ => MFS_FCB_LEN,
+    }
+}
+
+/// Open files as (reference number, FCB address); free FCBs have file number 0.
+pub fn open_files() -> Vec<(u16, u32)> {
+    let buffer = peek_u32(FCBS_PTR) & 0xFFFFFF;
+    if buffer == 0 {
+        return Vec::new();
+    }
+    let end = peek_u16(buffer) as u32;
+    let len = fcb_len();
+    (0..)
+        .map(|i| 2 + i * len)
+        .take_while(|&offset| offset + len <= end)
+        .filter(|&offset| peek_u32(buffer + offset + FCB_FL_NUM) != 0)
+        .map(|offset| (offset as u16, buffer + offset))
+        .collect()
+}
+
+fn volume_name(vcb: u32) -> String {
+    if vcb == 0 { "?".to_string() } else { peek_pstring(vcb + VCB_VN) }
+}
+
+/// File number, fork, mark, EOF and name (HFS only) of an open file.
+pub fn describe_fcb(refnum: u16, fcb: u32) -> String {
+    let flags = peek_u8(fcb + FCB_MD_R_BYT);
+    let name = if fcb_len() > FCB_C_NAME { format!("  \"{}\"", peek_pstring(fcb + FCB_C_NAME)) } else { String::new() };
+    format!(
+        "refNum {:4}  file {:6}  {} fork  mark {}  EOF {}  physical {}  {}{}  on \"{}\"{}",
+        refnum, peek_u32(fcb + FCB_FL_NUM),
+        if flags & 0x02 != 0 { "resource" } else { "data" },
+        peek_u32(fcb + FCB_CR_PS), peek_u32(fcb + FCB_EOF), peek_u32(fcb + FCB_P_LEN),
+        if flags & 0x01 != 0 { "write" } else { "read-only" },
+        if flags & 0x80 != 0 { " dirty" } else { "" },
+        volume_name(peek_u32(fcb + FCB_V_PTR) & 0xFFFFFF), name
+    )
+}
+
+/// Mounted volumes, in VCB queue order.
+pub fn volumes() -> Vec<u32> {
+    queues::elements(VCB_QUEUE)
+}
+
+/// Name, drive, reference numbers, file count and free space of a volume.
+pub fn describe_vcb(vcb: u32) -> String {
+    let block_size = peek_u32(vcb + VCB_AL_BLK_SIZ);
+    let free = peek_u16(vcb + VCB_FREE_BKS) as u32 * block_size;
+    let total = peek_u16(vcb + VCB_NM_BLKS) as u32 * block_size;
+    format!(
+        "0x{:06X}  \"{}\"  drive {}  driver {}  vRefNum {}  {} files  {}K free of {}K{}",
+        vcb, volume_name(vcb), peek_u16(vcb + VCB_DRV_NUM) as i16, peek_u16(vcb + VCB_D_REF_NUM) as i16,
+        peek_u16(vcb + VCB_V_REF_NUM) as i16, peek_u16(vcb + VCB_NM_FLS), free / 1024, total / 1024,
+        // Bit 15 of vcbAtrb is the software lock, bit 7 the hardware lock
+        if peek_u16(vcb + VCB_ATRB) & 0x8080 != 0 { "  locked" } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn describe_volume_and_file() {
+        let vcb = 0x36000;
+        put(vcb + VCB_ATRB, b"\x80\x00\x00\x03");
+        put(vcb + VCB_NM_BLKS, b"\x01\x87\x00\x00\x04\x00");
+        put(vcb + VCB_FREE_BKS, b"\x00\x64\x04Disk");
+        put(vcb + VCB_DRV_NUM, b"\x00\x01\xFF\xFB\x00\x00\xFF\xFF");
+        assert_eq!(describe_vcb(vcb), "0x036000  \"Disk\"  drive 1  driver -5  vRefNum -1  3 files  100K free of 391K  locked");
+
+        // An MFS FCB (no name) for a resource fork open for writing
+        let fcb = 0x36100;
+        put(fcb, b"\x00\x00\x00\x10\x03\x00\x00\x00\x00\x00\x02\x00\x00\x00\x04\x00\x00\x00\x00\x64\x00\x03\x60\x00");
+        assert_eq!(
+            describe_fcb(2, fcb),
+            "refNum    2  file     16  resource fork  mark 100  EOF 512  physical 1024  write  on \"Disk\""
+        );
+    }
+}
@@ -0,0 +1,444 @@
+/* Minimal GDB remote serial protocol server.
+ *
+ * The socket is serviced on its own thread, but Musashi and the memory map
+ * belong to the emulation thread, so every request that touches the machine
+ * is shipped over as a job and run from `poll()` between frames.
+ */
+
+use crate::cpu::{execute_instruction, get_reg, reg_by_name, set_reg};
+use crate::debugger;
+use crate::memory::{peek_u8, poke_u8};
+use crate::watchpoint::{self, WatchKind};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref JOBS: Mutex<Option<Receiver<Job>>> = Mutex::new(None);
+    static ref STOP_NOTIFY: Mutex<Option<Sender<()>>> = Mutex::new(None);
+}
+
+// GDB's m68k register numbering: d0-d7, a0-a7, ps, pc
+const REGISTERS: [&str; 18] = [
+    "d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7",
+    "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+    "sr", "pc",
+];
+
+// Largest packet we accept or send, as advertised in qSupported
+const PACKET_SIZE: u32 = 0x4000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>m68k</architecture>
+  <feature name="org.gnu.gdb.m68k.core">
+    <reg name="d0" bitsize="32"/>
+    <reg name="d1" bitsize="32"/>
+    <reg name="d2" bitsize="32"/>
+    <reg name="d3" bitsize="32"/>
+    <reg name="d4" bitsize="32"/>
+    <reg name="d5" bitsize="32"/>
+    <reg name="d6" bitsize="32"/>
+    <reg name="d7" bitsize="32"/>
+    <reg name="a0" bitsize="32" type="data_ptr"/>
+    <reg name="a1" bitsize="32" type="data_ptr"/>
+    <reg name="a2" bitsize="32" type="data_ptr"/>
+    <reg name="a3" bitsize="32" type="data_ptr"/>
+    <reg name="a4" bitsize="32" type="data_ptr"/>
+    <reg name="a5" bitsize="32" type="data_ptr"/>
+    <reg name="fp" bitsize="32" type="data_ptr"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="ps" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Listens for GDB on localhost:`port`.
+pub fn start(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (tx, rx) = mpsc::channel();
+    *JOBS.lock().unwrap() = Some(rx);
+    info!("GDB stub listening on 127.0.0.1:{}", port);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    info!("GDB connected from {:?}", stream.peer_addr());
+                    if let Err(e) = serve(stream, &tx) {
+                        warn!("GDB connection ended: {}", e);
+                    }
+                    *STOP_NOTIFY.lock().unwrap() = None;
+                }
+                Err(e) => warn!("GDB accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Runs pending GDB requests; call from the emulation thread each frame.
+pub fn poll() {
+    let jobs: Vec<Job> = match JOBS.lock().unwrap().as_ref() {
+        Some(rx) => rx.try_iter().collect(),
+        None => return,
+    };
+    for job in jobs {
+        job();
+    }
+}
+
+/// Tells an attached GDB that the machine has stopped.
+pub fn notify_stop() {
+    if let Some(tx) = STOP_NOTIFY.lock().unwrap().as_ref() {
+        let _ = tx.send(());
+    }
+}
+
+fn on_main<T: Send + 'static>(jobs: &Sender<Job>, f: impl FnOnce() -> T + Send + 'static) -> io::Result<T> {
+    let (tx, rx) = mpsc::channel();
+    jobs.send(Box::new(move || {
+        let _ = tx.send(f());
+    }))
+    .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "emulator gone"))?;
+    rx.recv().map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "emulator gone"))
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn receive(&mut self) -> io::Result<Incoming> {
+        loop {
+            match self.read_byte()? {
+                0x03 => return Ok(Incoming::Interrupt),
+                b'$' => match read_packet(&mut self.reader)? {
+                    Some(packet) => {
+                        self.writer.write_all(b"+")?;
+                        return Ok(Incoming::Packet(packet));
+                    }
+                    None => self.writer.write_all(b"-")?,
+                },
+                // Acks and anything else between packets
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.writer.write_all(&frame(data))?;
+        self.writer.flush()
+    }
+
+    /// Blocks until the machine stops or GDB sends ^C, then reports the stop.
+    fn wait_for_stop(&mut self, jobs: &Sender<Job>, stops: &Receiver<()>) -> io::Result<()> {
+        self.reader.get_ref().set_read_timeout(Some(Duration::from_millis(20)))?;
+        loop {
+            match stops.recv_timeout(Duration::from_millis(20)) {
+                Ok(()) => break,
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            match self.read_byte() {
+                Ok(0x03) => {
+                    on_main(jobs, || debugger::request_break("GDB interrupt"))?;
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.reader.get_ref().set_read_timeout(None)?;
+        self.send(&stop_reply())
+    }
+}
+
+/// Reads the rest of a packet after its `$`; None if the checksum doesn't match.
+fn read_packet(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut data = Vec::new();
+    reader.read_until(b'#', &mut data)?;
+    data.pop();
+    let mut sum = [0u8; 2];
+    reader.read_exact(&mut sum)?;
+    let expected = u8::from_str_radix(std::str::from_utf8(&sum).unwrap_or(""), 16).ok();
+    Ok((expected == Some(checksum(&data))).then(|| String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// `$data#xx` with the protocol's special characters escaped.
+fn frame(data: &str) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data.as_bytes() {
+        if matches!(b, b'#' | b'$' | b'}' | b'*') {
+            escaped.push(b'}');
+            escaped.push(b ^ 0x20);
+        } else {
+            escaped.push(b);
+        }
+    }
+    let mut out = vec![b'$'];
+    out.extend_from_slice(&escaped);
+    out.extend_from_slice(format!("#{:02x}", checksum(&escaped)).as_bytes());
+    out
+}
+
+fn stop_reply() -> String {
+    match watchpoint::take_last_hit() {
+        Some(hit) => {
+            let kind = match hit.kind {
+                WatchKind::Read => "rwatch",
+                _ => "watch",
+            };
+            format!("T05{}:{:x};", kind, hit.addr)
+        }
+        None => "S05".to_string(),
+    }
+}
+
+fn serve(stream: TcpStream, jobs: &Sender<Job>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut conn = Connection {
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream,
+    };
+    let (stop_tx, stop_rx) = mpsc::channel();
+    *STOP_NOTIFY.lock().unwrap() = Some(stop_tx);
+
+    // GDB expects a halted target on attach
+    let running = on_main(jobs, || {
+        let running = !debugger::is_paused();
+        if running {
+            debugger::request_break("GDB attached");
+        }
+        running
+    })?;
+    if running {
+        let _ = stop_rx.recv_timeout(Duration::from_secs(5));
+    }
+    let _ = watchpoint::take_last_hit();
+
+    loop {
+        let packet = match conn.receive()? {
+            Incoming::Interrupt => continue,
+            Incoming::Packet(packet) => packet,
+        };
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => on_main(jobs, || REGISTERS.iter().map(|r| format!("{:08x}", read_register(r))).collect())?,
+            "G" => {
+                let values: Vec<u32> = (0..args.len() / 8)
+                    .filter_map(|i| u32::from_str_radix(&args[i * 8..i * 8 + 8], 16).ok())
+                    .collect();
+                on_main(jobs, move || {
+                    for (name, value) in REGISTERS.iter().zip(values) {
+                        write_register(name, value);
+                    }
+                })?;
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|n| REGISTERS.get(n)) {
+                Some(name) => on_main(jobs, move || format!("{:08x}", read_register(name)))?,
+                None => "E01".to_string(),
+            },
+            "P" => match parse_register_write(args) {
+                Some((name, value)) => {
+                    on_main(jobs, move || write_register(name, value))?;
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "m" => match parse_addr_len(args) {
+                // Two hex digits per byte have to fit in one reply packet
+                Some((addr, len)) => on_main(jobs, move || {
+                    let len = len.min(PACKET_SIZE / 2);
+                    (0..len).map(|i| format!("{:02x}", peek_u8(addr.wrapping_add(i)))).collect()
+                })?,
+                None => "E01".to_string(),
+            },
+            "M" => match args.split_once(':').and_then(|(range, data)| Some((parse_addr_len(range)?, decode_hex(data)?))) {
+                Some(((addr, _), bytes)) => on_main(jobs, move || {
+                    let ok = bytes.iter().enumerate().all(|(i, &b)| poke_u8(addr.wrapping_add(i as u32), b));
+                    if ok { "OK" } else { "E02" }.to_string()
+                })?,
+                None => "E01".to_string(),
+            },
+            "c" => {
+                while stop_rx.try_recv().is_ok() {}
+                let running = on_main(jobs, || !debugger::is_paused() || debugger::resume())?;
+                if running {
+                    conn.wait_for_stop(jobs, &stop_rx)?;
+                } else {
+                    conn.send(&stop_reply())?;
+                }
+                continue;
+            }
+            "s" => {
+                on_main(jobs, || {
+                    execute_instruction();
+                    let _ = debugger::take_pending();
+                })?;
+                stop_reply()
+            }
+            "Z" | "z" => breakpoint_packet(cmd == "Z", args, jobs)?,
+            "D" => {
+                on_main(jobs, || debugger::is_paused() && debugger::resume())?;
+                conn.send("OK")?;
+                return Ok(());
+            }
+            "k" => {
+                on_main(jobs, || debugger::is_paused() && debugger::resume())?;
+                return Ok(());
+            }
+            "H" => "OK".to_string(),
+            "q" => query_packet(args),
+            _ => String::new(),
+        };
+        conn.send(&reply)?;
+    }
+}
+
+fn query_packet(args: &str) -> String {
+    if args.starts_with("Supported") {
+        format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+    } else if args == "Attached" {
+        "1".to_string()
+    } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        match range.split_once(',') {
+            Some((offset, len)) => {
+                let offset = usize::from_str_radix(offset, 16).unwrap_or(0).min(TARGET_XML.len());
+                let len = usize::from_str_radix(len, 16).unwrap_or(0);
+                let end = (offset + len).min(TARGET_XML.len());
+                let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", prefix, &TARGET_XML[offset..end])
+            }
+            None => "E01".to_string(),
+        }
+    } else {
+        String::new()
+    }
+}
+
+fn breakpoint_packet(insert: bool, args: &str, jobs: &Sender<Job>) -> io::Result<String> {
+    let mut parts = args.split(',');
+    let (Some(kind), Some(addr), Some(len)) = (parts.next(), parts.next(), parts.next()) else {
+        return Ok("E01".to_string());
+    };
+    let (Ok(addr), Ok(len)) = (u32::from_str_radix(addr, 16), u32::from_str_radix(len, 16)) else {
+        return Ok("E01".to_string());
+    };
+    let kinds: &[WatchKind] = match kind {
+        // No hardware breakpoints; an empty reply tells GDB they aren't supported
+        "0" => {
+            return on_main(jobs, move || {
+                if insert {
+                    debugger::add_breakpoint(addr);
+                } else {
+                    debugger::remove_breakpoint(addr);
+                }
+                "OK".to_string()
+            });
+        }
+        "2" => &[WatchKind::Write],
+        "3" => &[WatchKind::Read],
+        "4" => &[WatchKind::Read, WatchKind::Write],
+        _ => return Ok(String::new()),
+    };
+    for &kind in kinds {
+        if insert {
+            watchpoint::add(addr, len, kind);
+        } else {
+            watchpoint::remove_matching(addr, len, kind);
+        }
+    }
+    Ok("OK".to_string())
+}
+
+fn read_register(name: &str) -> u32 {
+    reg_by_name(name).map(get_reg).unwrap_or(0)
+}
+
+fn write_register(name: &str, value: u32) {
+    if let Some(reg) = reg_by_name(name) {
+        set_reg(reg, value);
+    }
+}
+
+fn parse_register_write(args: &str) -> Option<(&'static str, u32)> {
+    let (n, value) = args.split_once('=')?;
+    let name = REGISTERS.get(usize::from_str_radix(n, 16).ok()?)?;
+    Some((name, u32::from_str_radix(value, 16).ok()?))
+}
+
+fn parse_addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, u32::from_str_radix(len, 16).ok()?))
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    (0..data.len() / 2)
+        .map(|i| u8::from_str_radix(data.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_packet_checks_checksum() {
+        let mut good = Cursor::new(b"m400,4#61".to_vec());
+        assert_eq!(read_packet(&mut good).unwrap(), Some("m400,4".to_string()));
+        let mut bad = Cursor::new(b"m400,4#00".to_vec());
+        assert_eq!(read_packet(&mut bad).unwrap(), None);
+    }
+
+    #[test]
+    fn frame_escapes_and_sums() {
+        assert_eq!(frame("OK"), b"$OK#9a".to_vec());
+        assert_eq!(frame("a#b"), b"$a}\x03b#43".to_vec());
+        assert_eq!(frame(""), b"$#00".to_vec());
+    }
+
+    #[test]
+    fn argument_parsing() {
+        assert_eq!(parse_addr_len("400,10"), Some((0x400, 0x10)));
+        assert_eq!(parse_addr_len("400"), None);
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(parse_register_write("11=00400000"), Some(("pc", 0x400000)));
+        assert_eq!(parse_register_write("20=0"), None);
+    }
+
+    #[test]
+    fn target_xml_is_served_in_chunks() {
+        assert_eq!(query_packet("Xfer:features:read:target.xml:0,5"), "m<?xml");
+        let last = query_packet(&format!("Xfer:features:read:target.xml:{:x},100", TARGET_XML.len() - 3));
+        assert_eq!(last, format!("l{}", &TARGET_XML[TARGET_XML.len() - 3..]));
+    }
+}
@@ -0,0 +1,235 @@
+/* Memory Manager heap zones.
+ *
+ * Walks a zone from its first block (heapData, just past the 52-byte zone
+ * header) to bkLim.  Each block starts with an 8-byte header: the tag in the
+ * top two bits of the first byte (free, nonrelocatable or relocatable), the
+ * size correction in its low nibble and the physical size in the low three
+ * bytes; the second long is the master pointer's offset from the zone for
+ * relocatable blocks and the zone itself for nonrelocatable ones.  The
+ * master pointer's high byte carries the locked, purgeable and resource
+ * flags.  Walking stops at the first block that fails a sanity check.
+ */
+
+use crate::memory::{peek_u32, peek_u8, RAM_SIZE};
+use crate::symbols::format_address;
+use std::fmt;
+
+pub const SYS_ZONE: u32 = 0x2A6;
+pub const APPL_ZONE: u32 = 0x2AA;
+// Zone header fields
+const BK_LIM: u32 = 0;
+const ZCB_FREE: u32 = 12;
+const HEAP_DATA: u32 = 52;
+const BLOCK_HEADER: u32 = 8;
+const MIN_BLOCK: u32 = 12;
+const MAX_BLOCKS: usize = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Free,
+    Nonrelocatable,
+    Relocatable,
+}
+
+pub struct Block {
+    pub addr: u32,
+    pub size: u32,
+    pub kind: BlockKind,
+    // Bytes of padding at the end of the block
+    pub correction: u32,
+    pub master: Option<u32>,
+    // Master pointer flags: bit 7 locked, bit 6 purgeable, bit 5 resource
+    pub flags: u8,
+}
+
+pub struct Zone {
+    pub addr: u32,
+    pub bk_lim: u32,
+    pub zcb_free: u32,
+    pub blocks: Vec<Block>,
+    /// The first corrupt block and what is wrong with it.
+    pub error: Option<(u32, String)>,
+}
+
+impl Block {
+    /// Address of the block's contents, as held by a pointer or master pointer.
+    pub fn data(&self) -> u32 {
+        self.addr + BLOCK_HEADER
+    }
+
+    pub fn logical_size(&self) -> u32 {
+        self.size.saturating_sub(BLOCK_HEADER + self.correction)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            BlockKind::Free => "free",
+            BlockKind::Nonrelocatable => "ptr",
+            BlockKind::Relocatable => "handle",
+        };
+        write!(f, "0x{:06X}  {:<6}  {:>8}", self.data(), kind, self.logical_size())?;
+        if let Some(master) = self.master {
+            write!(f, "  mp 0x{:06X}", master)?;
+            for (bit, name) in [(0x80, "locked"), (0x40, "purgeable"), (0x20, "resource")] {
+                if self.flags & bit != 0 {
+                    write!(f, " {}", name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Zone {
+    pub fn free_bytes(&self) -> u32 {
+        self.blocks.iter().filter(|b| b.kind == BlockKind::Free).map(|b| b.size).sum()
+    }
+
+    /// One-line summary: extent, block counts and free space.
+    pub fn summary(&self) -> String {
+        let count = |kind| self.blocks.iter().filter(|b| b.kind == kind).count();
+        format!(
+            "zone 0x{:06X}..0x{:06X}: {} handles, {} pointers, {} free blocks, {} bytes free (zcbFree {})",
+            self.addr, self.bk_lim,
+            count(BlockKind::Relocatable), count(BlockKind::Nonrelocatable), count(BlockKind::Free),
+            self.free_bytes(), self.zcb_free
+        )
+    }
+}
+
+/// Logical size of the heap block whose contents start at `data`, from its header.
+pub fn block_size(data: u32) -> u32 {
+    let header = data.wrapping_sub(BLOCK_HEADER);
+    let correction = (peek_u8(header) & 0x0F) as u32;
+    (peek_u32(header) & 0xFFFFFF).saturating_sub(BLOCK_HEADER + correction)
+}
+
+/// The system and application zones, skipping the application zone when it is the same.
+pub fn zones() -> Vec<(&'static str, u32)> {
+    let sys = peek_u32(SYS_ZONE) & 0xFFFFFF;
+    let app = peek_u32(APPL_ZONE) & 0xFFFFFF;
+    let mut zones = vec![("SysZone", sys)];
+    if app != sys {
+        zones.push(("ApplZone", app));
+    }
+    zones
+}
+
+/// Walks the zone whose header is at `zone`.
+pub fn walk(zone: u32) -> Zone {
+    let bk_lim = peek_u32(zone.wrapping_add(BK_LIM)) & 0xFFFFFF;
+    let mut result = Zone {
+        addr: zone,
+        bk_lim,
+        zcb_free: peek_u32(zone.wrapping_add(ZCB_FREE)),
+        blocks: Vec::new(),
+        error: None,
+    };
+    let data = zone.checked_add(HEAP_DATA)
+        .filter(|&data| zone != 0 && zone & 1 == 0 && data < bk_lim && bk_lim < RAM_SIZE as u32);
+    let Some(mut addr) = data else {
+        result.error = Some((zone, format!("bad zone header: bkLim 0x{:06X}", bk_lim)));
+        return result;
+    };
+    while addr < bk_lim && result.blocks.len() < MAX_BLOCKS {
+        match read_block(zone, addr, bk_lim) {
+            Ok(block) => {
+                addr += block.size;
+                result.blocks.push(block);
+            }
+            Err(problem) => {
+                result.error = Some((addr, problem));
+                return result;
+            }
+        }
+    }
+    if addr != bk_lim {
+        result.error = Some((addr, format!("last block ends at 0x{:06X}, not at bkLim", addr)));
+    }
+    result
+}
+
+fn read_block(zone: u32, addr: u32, bk_lim: u32) -> Result<Block, String> {
+    let tag = peek_u8(addr);
+    let size = peek_u32(addr) & 0xFFFFFF;
+    let correction = (tag & 0x0F) as u32;
+    let kind = match tag >> 6 {
+        0 => BlockKind::Free,
+        1 => BlockKind::Nonrelocatable,
+        2 => BlockKind::Relocatable,
+        _ => return Err(format!("bad tag byte ${:02X}", tag)),
+    };
+    if size < MIN_BLOCK || size & 1 != 0 {
+        return Err(format!("bad block size {}", size));
+    }
+    if addr + size > bk_lim {
+        return Err(format!("block of {} bytes runs past bkLim 0x{:06X}", size, bk_lim));
+    }
+    if kind != BlockKind::Free && correction > size - BLOCK_HEADER {
+        return Err(format!("size correction {} larger than the block", correction));
+    }
+    let mut block = Block { addr, size, kind, correction, master: None, flags: 0 };
+    match kind {
+        BlockKind::Relocatable => {
+            let master = zone.wrapping_add(peek_u32(addr + 4));
+            if master < zone + HEAP_DATA || master >= bk_lim {
+                return Err(format!("relative handle ${:08X} points outside the zone", peek_u32(addr + 4)));
+            }
+            let pointer = peek_u32(master);
+            if pointer & 0xFFFFFF != block.data() {
+                return Err(format!("master pointer at 0x{:06X} holds 0x{:06X}, not this block", master, pointer & 0xFFFFFF));
+            }
+            block.master = Some(master);
+            block.flags = (pointer >> 24) as u8;
+        }
+        BlockKind::Nonrelocatable => {
+            let owner = peek_u32(addr + 4) & 0xFFFFFF;
+            if owner != zone {
+                return Err(format!("nonrelocatable block belongs to zone 0x{:06X}", owner));
+            }
+        }
+        BlockKind::Free => {}
+    }
+    Ok(block)
+}
+
+/// Describes where the walk of `zone` failed, if it did.
+pub fn describe_error(zone: &Zone) -> Option<String> {
+    zone.error.as_ref().map(|(addr, problem)| format!("Corrupt block at {}: {}", format_address(*addr), problem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn walk_decodes_blocks() {
+        // Zone header: bkLim, zcbFree
+        put(0x33000, b"\x00\x03\x30\x60");
+        put(0x3300C, b"\x00\x00\x00\x0C");
+        // Pointer block holding the master pointer, handle block, free block
+        put(0x33034, b"\x40\x00\x00\x10\x00\x03\x30\x00\x80\x03\x30\x4C");
+        put(0x33044, b"\x80\x00\x00\x10\x00\x00\x00\x3C");
+        put(0x33054, b"\x00\x00\x00\x0C");
+        let zone = walk(0x33000);
+        assert!(zone.error.is_none());
+        assert_eq!(zone.blocks.len(), 3);
+        assert_eq!(zone.free_bytes(), 12);
+        assert_eq!(zone.blocks[1].to_string(), "0x03304C  handle         8  mp 0x03303C locked");
+    }
+
+    #[test]
+    fn walk_rejects_bad_zones() {
+        assert!(walk(0xFFFFFFF0).error.is_some());
+        assert!(walk(0x33001).error.is_some());
+    }
+}
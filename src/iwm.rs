@@ -37,4 +37,9 @@ impl Iwm {
         log::info!("[IWM: RD {} <- {:02x}]", r, data);
         data
     }
+
+    pub fn dump(&self) -> String {
+        let regs: Vec<String> = self.regs.iter().map(|r| format!("{:02X}", r)).collect();
+        format!("IWM regs: {}", regs.join(" "))
+    }
 }
@@ -0,0 +1,68 @@
+/* Macintosh 128K emulator.
+ *
+ * The emulator binary is a front end over these modules.  Test harnesses
+ * can link the library instead and watch the guest through `events`.
+ */
+
+pub mod cpu;
+pub mod memory;
+pub mod video;
+pub mod via;
+pub mod iwm;
+pub mod watchpoint;
+pub mod debugger;
+pub mod gdbstub;
+pub mod traps;
+pub mod calltrace;
+pub mod profiler;
+pub mod coverage;
+pub mod symbols;
+pub mod macsbug;
+pub mod backtrace;
+pub mod crash;
+pub mod events;
+pub mod watchdog;
+pub mod lowmem;
+pub mod heap;
+pub mod resources;
+pub mod windows;
+pub mod queues;
+pub mod files;
+pub mod drivers;
+pub mod patches;
+pub mod segments;
+pub mod memsearch;
+pub mod shadow;
+pub mod disasm;
+
+pub use events::MachineEvent;
+
+use log::{error, info};
+use std::sync::Mutex;
+
+// Memory written to a file on exit: (path, start, length or all of RAM)
+static EXIT_DUMP: Mutex<Option<(String, u32, Option<u32>)>> = Mutex::new(None);
+
+/// Writes `len` bytes (up to the end of RAM if None) from `start` to `path` when the emulator exits.
+pub fn set_exit_dump(path: &str, start: u32, len: Option<u32>) {
+    *EXIT_DUMP.lock().unwrap() = Some((path.to_string(), start, len));
+}
+
+/// Flushes any reports that are written when the emulator exits.
+pub fn shutdown() {
+    profiler::finish();
+    coverage::finish();
+    if let Some((path, start, len)) = EXIT_DUMP.lock().unwrap().take() {
+        let result = match len {
+            None if start == 0 => memory::save_ram(&path),
+            _ => {
+                let len = len.unwrap_or_else(|| memory::ram_end().saturating_sub(start));
+                memory::save_memory(&path, start, len).map(|()| len)
+            }
+        };
+        match result {
+            Ok(len) => info!("Wrote {} bytes from 0x{:06X} to {}", len, start, path),
+            Err(e) => error!("Failed to write {}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+/* Low-memory global names for the 64K and 128K ROMs.
+ *
+ * Addresses and sizes follow SysEqu.a; sizes are in bytes, and globals that
+ * are records or arrays (queue headers, key maps) use their full size.
+ * Values are decoded by size, with the few strings, queue headers, rects
+ * and counters listed below decoded accordingly.
+ */
+
+use crate::memory::{peek_pstring, peek_u16, peek_u32, peek_u8};
+use crate::symbols;
+
+pub struct Global {
+    pub addr: u32,
+    pub size: u32,
+    pub name: &'static str,
+}
+
+macro_rules! globals {
+    ($(($addr:expr, $size:expr, $name:expr)),* $(,)?) => {
+        &[$(Global { addr: $addr, size: $size, name: $name }),*]
+    };
+}
+
+// Sorted by address
+pub const GLOBALS: &[Global] = globals![
+    (0x100, 2, "MonkeyLives"), (0x102, 2, "ScrVRes"), (0x104, 2, "ScrHRes"), (0x106, 2, "ScreenRow"),
+    (0x108, 4, "MemTop"), (0x10C, 4, "BufPtr"), (0x110, 4, "StkLowPt"), (0x114, 4, "HeapEnd"),
+    (0x118, 4, "TheZone"), (0x11C, 4, "UTableBase"), (0x120, 4, "MacJmp"), (0x124, 4, "DskRtnAdr"),
+    (0x128, 4, "PollRtnAdr"), (0x12C, 1, "DskVerify"), (0x12D, 1, "LoadTrap"), (0x12E, 1, "MmInOK"),
+    (0x12F, 1, "CPUFlag"), (0x130, 4, "ApplLimit"), (0x134, 4, "SonyVars"), (0x138, 2, "PWMValue"),
+    (0x13A, 4, "PollStack"), (0x13E, 4, "PollProc"), (0x142, 2, "DskErr"), (0x144, 2, "SysEvtMask"),
+    (0x146, 4, "SysEvtBuf"), (0x14A, 10, "EventQueue"), (0x154, 2, "EvtBufCnt"), (0x156, 4, "RndSeed"),
+    (0x15A, 2, "SysVersion"), (0x15C, 1, "SEvtEnb"), (0x15D, 1, "DSWndUpdate"), (0x15E, 1, "FontFlag"),
+    (0x15F, 1, "IntFlag"), (0x160, 10, "VBLQueue"), (0x16A, 4, "Ticks"), (0x16E, 4, "MBTicks"),
+    (0x172, 1, "MBState"), (0x173, 1, "Tocks"), (0x174, 8, "KeyMap"), (0x17C, 8, "KeypadMap"),
+    (0x184, 2, "KeyLast"), (0x186, 4, "KeyTime"), (0x18A, 4, "KeyRepTime"), (0x18E, 2, "KeyThresh"),
+    (0x190, 2, "KeyRepThresh"), (0x192, 32, "Lvl1DT"), (0x1B2, 32, "Lvl2DT"), (0x1D2, 2, "UnitNtryCnt"),
+    (0x1D4, 4, "VIA"), (0x1D8, 4, "SCCRd"), (0x1DC, 4, "SCCWr"), (0x1E0, 4, "IWM"),
+    (0x1E4, 20, "GetParam"), (0x1F8, 20, "SysParam"), (0x20C, 4, "Time"), (0x210, 2, "BootDrive"),
+    (0x212, 2, "JShell"), (0x214, 2, "SFSaveDisk"), (0x21E, 1, "KbdType"), (0x220, 2, "MemErr"),
+    (0x260, 1, "SdVolume"), (0x262, 4, "SoundPtr"), (0x266, 4, "SoundBase"), (0x26A, 16, "SoundVBL"),
+    (0x27A, 4, "SoundDCE"), (0x27E, 1, "SoundActive"), (0x27F, 1, "SoundLevel"), (0x280, 2, "CurPitch"),
+    (0x28E, 2, "ROM85"), (0x290, 1, "PortAUse"), (0x291, 1, "PortBUse"), (0x2A6, 4, "SysZone"),
+    (0x2AA, 4, "ApplZone"), (0x2AE, 4, "ROMBase"), (0x2B2, 4, "RAMBase"), (0x2BA, 4, "DSAlertTab"),
+    (0x2BE, 16, "ExtStsDT"), (0x2CE, 1, "SCCASts"), (0x2CF, 1, "SCCBSts"), (0x2D0, 16, "SerialVars"),
+    (0x2E0, 4, "ABusVars"), (0x2F0, 16, "FinderName"), (0x308, 10, "DrvQHdr"), (0x312, 4, "PWMBuf2"),
+    (0x31A, 4, "Lo3Bytes"), (0x31E, 4, "MinStack"), (0x322, 4, "DefltStack"), (0x326, 2, "MMDefFlags"),
+    (0x328, 4, "GZRootHnd"), (0x32C, 4, "GZRootPtr"), (0x330, 4, "GZMoveHnd"), (0x334, 4, "DSDrawProc"),
+    (0x338, 4, "EjectNotify"), (0x33C, 4, "IAZNotify"), (0x34E, 4, "FCBSPtr"), (0x352, 4, "DefVCBPtr"),
+    (0x356, 10, "VCBQHdr"), (0x360, 10, "FSQHdr"), (0x3F6, 2, "FSFCBLen"), (0x3F8, 8, "DSAlertRect"),
+    (0x800, 4, "JHideCursor"), (0x804, 4, "JShowCursor"), (0x808, 4, "JShieldCursor"), (0x80C, 4, "JScrnAddr"),
+    (0x810, 4, "JScrnSize"), (0x814, 4, "JInitCrsr"), (0x818, 4, "JSetCrsr"), (0x81C, 4, "JCrsrObscure"),
+    (0x824, 4, "ScrnBase"), (0x828, 4, "MTemp"), (0x82C, 4, "RawMouse"), (0x830, 4, "Mouse"),
+    (0x834, 8, "CrsrPin"), (0x83C, 8, "CrsrRect"), (0x844, 68, "TheCrsr"), (0x888, 4, "CrsrAddr"),
+    (0x8CE, 1, "CrsrNew"), (0x8CF, 1, "CrsrCouple"), (0x8D0, 2, "CrsrState"), (0x8D2, 1, "CrsrObscure"),
+    (0x8D3, 1, "CrsrScale"), (0x8D6, 4, "MouseMask"), (0x8DA, 4, "MouseOffset"), (0x8DE, 2, "JournalFlag"),
+    (0x8E0, 4, "JSwapFont"), (0x8E4, 4, "JFontInfo"), (0x8E8, 2, "JournalRef"), (0x8EC, 2, "CrsrThresh"),
+    (0x8EE, 4, "JCrsrTask"), (0x8F2, 1, "WWExist"), (0x8F3, 1, "QDExist"), (0x8F4, 4, "JFetch"),
+    (0x8F8, 4, "JStash"), (0x8FC, 4, "JIODone"), (0x900, 2, "CurApRefNum"), (0x902, 1, "LaunchFlag"),
+    (0x904, 4, "CurrentA5"), (0x908, 4, "CurStackBase"), (0x910, 32, "CurApName"), (0x930, 4, "SaveSegHandle"),
+    (0x934, 2, "CurJTOffset"), (0x936, 2, "CurPageOption"), (0x938, 2, "HiliteMode"), (0x93A, 10, "LoaderPBlock"),
+    (0x944, 2, "PrintErr"), (0x960, 4, "ScrapSize"), (0x964, 4, "ScrapHandle"), (0x968, 2, "ScrapCount"),
+    (0x96A, 2, "ScrapState"), (0x96C, 4, "ScrapName"), (0x980, 4, "ROMFont0"), (0x984, 2, "ApFontID"),
+    (0x9D6, 4, "WindowList"), (0x9DA, 2, "SaveUpdate"), (0x9DC, 2, "PaintWhite"), (0x9DE, 4, "WMgrPort"),
+    (0x9E6, 4, "OldStructure"), (0x9EA, 4, "OldContent"), (0x9EE, 4, "GrayRgn"), (0x9F2, 4, "SaveVisRgn"),
+    (0x9F6, 4, "DragHook"), (0x9FA, 8, "Scratch8"), (0xA02, 4, "OneOne"), (0xA06, 4, "MinusOne"),
+    (0xA0A, 2, "TopMenuItem"), (0xA0C, 2, "AtMenuBottom"), (0xA0E, 14, "IconBitmap"), (0xA1C, 4, "MenuList"),
+    (0xA20, 2, "MBarEnable"), (0xA22, 2, "CurDeKind"), (0xA24, 2, "MenuFlash"), (0xA26, 2, "TheMenu"),
+    (0xA28, 4, "SavedHandle"), (0xA2C, 4, "MBarHook"), (0xA30, 4, "MenuHook"), (0xA34, 8, "DragPattern"),
+    (0xA3C, 8, "DeskPattern"), (0xA44, 2, "DragFlag"), (0xA46, 4, "CurDragAction"), (0xA4A, 6, "FPState"),
+    (0xA50, 4, "TopMapHndl"), (0xA54, 4, "SysMapHndl"), (0xA58, 2, "SysMap"), (0xA5A, 2, "CurMap"),
+    (0xA5C, 2, "ResReadOnly"), (0xA5E, 2, "ResLoad"), (0xA60, 2, "ResErr"), (0xA62, 1, "TaskLock"),
+    (0xA63, 1, "FScaleDisable"), (0xA64, 4, "CurActivate"), (0xA68, 4, "CurDeactive"), (0xA6C, 4, "DeskHook"),
+    (0xA70, 4, "TEDoText"), (0xA74, 4, "TERecal"), (0xA78, 12, "ApplScratch"), (0xA84, 4, "GhostWindow"),
+    (0xA88, 4, "CloseOrnHook"), (0xA8C, 4, "ResumeProc"), (0xA90, 4, "SaveProc"), (0xA94, 4, "SaveSP"),
+    (0xA98, 2, "ANumber"), (0xA9A, 2, "ACount"), (0xA9C, 4, "DABeeper"), (0xAA0, 16, "DAStrings"),
+    (0xAB0, 2, "TEScrpLength"), (0xAB4, 4, "TEScrpHandle"), (0xAB8, 32, "AppPacks"), (0xAD8, 20, "SysResName"),
+    (0xAEC, 4, "AppParmHandle"), (0xAF0, 2, "DSErrCode"), (0xAF2, 4, "ResErrProc"), (0xAF6, 4, "TEWdBreak"),
+    (0xAFA, 2, "DlgFont"), (0xAFC, 4, "LastTGlobal"),
+];
+
+/// The global covering `addr`, with the offset into it.
+pub fn containing(addr: u32) -> Option<(&'static Global, u32)> {
+    let index = GLOBALS.partition_point(|g| g.addr <= addr).checked_sub(1)?;
+    let global = &GLOBALS[index];
+    (addr < global.addr + global.size).then(|| (global, addr - global.addr))
+}
+
+const STRINGS: &[&str] = &["CurApName", "FinderName", "SysResName"];
+const QUEUES: &[&str] = &["EventQueue", "VBLQueue", "DrvQHdr", "VCBQHdr", "FSQHdr"];
+const RECTS: &[&str] = &["CrsrPin", "CrsrRect", "DSAlertRect"];
+// Longs that are counts or times rather than addresses
+const COUNTERS: &[&str] = &["Ticks", "MBTicks", "Time", "RndSeed", "KeyTime", "KeyRepTime", "ScrapSize"];
+
+/// `Name` or `Name+offset` for an address inside a global.
+pub fn describe(addr: u32) -> Option<String> {
+    containing(addr).map(|(g, offset)| if offset == 0 { g.name.to_string() } else { format!("{}+{}", g.name, offset) })
+}
+
+/// Looks a global up by name, ignoring case.
+pub fn by_name(name: &str) -> Option<&'static Global> {
+    GLOBALS.iter().find(|g| g.name.eq_ignore_ascii_case(name))
+}
+
+/// Globals whose name contains `filter`, ignoring case.
+pub fn matching(filter: &str) -> Vec<&'static Global> {
+    let filter = filter.to_ascii_lowercase();
+    GLOBALS.iter().filter(|g| g.name.to_ascii_lowercase().contains(&filter)).collect()
+}
+
+fn pointer(value: u32) -> String {
+    match symbols::symbolize(value) {
+        Some(sym) => format!("0x{:08X} <{}>", value, sym),
+        None => format!("0x{:08X}", value),
+    }
+}
+
+/// The global's current value, decoded.
+pub fn format_value(global: &Global) -> String {
+    let addr = global.addr;
+    let name = global.name;
+    if STRINGS.contains(&name) {
+        format!("\"{}\"", peek_pstring(addr))
+    } else if QUEUES.contains(&name) {
+        format!("flags=${:04X} head={} tail={}", peek_u16(addr), pointer(peek_u32(addr + 2)), pointer(peek_u32(addr + 6)))
+    } else if RECTS.contains(&name) {
+        let v = |i| peek_u16(addr + i) as i16;
+        format!("(top {}, left {}, bottom {}, right {})", v(0), v(2), v(4), v(6))
+    } else if COUNTERS.contains(&name) {
+        let value = peek_u32(addr);
+        format!("{} (0x{:08X})", value, value)
+    } else {
+        match global.size {
+            1 => format!("${:02X}", peek_u8(addr)),
+            2 => {
+                let value = peek_u16(addr);
+                format!("${:04X} ({})", value, value as i16)
+            }
+            4 => pointer(peek_u32(addr)),
+            size => (0..size).map(|i| format!("{:02X}", peek_u8(addr + i))).collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn globals_are_sorted_and_disjoint() {
+        for pair in GLOBALS.windows(2) {
+            assert!(pair[0].addr + pair[0].size <= pair[1].addr, "{} overlaps {}", pair[0].name, pair[1].name);
+        }
+    }
+
+    #[test]
+    fn lookup_by_address_and_name() {
+        assert_eq!(describe(0x16A).as_deref(), Some("Ticks"));
+        assert_eq!(describe(0x176).as_deref(), Some("KeyMap+2"));
+        assert_eq!(describe(0x21A), None);
+        assert_eq!(by_name("dserrcode").map(|g| g.addr), Some(0xAF0));
+        assert_eq!(matching("queue").len(), 2);
+    }
+
+    #[test]
+    fn values_decode_by_kind() {
+        put(0x32000, b"\xFF\xFE\x00\x00\x12\x34");
+        put(0x32010, b"\x05Hello");
+        put(0x32020, b"\x00\x00\x01\x00");
+        let global = |addr, size, name| Global { addr, size, name };
+        assert_eq!(format_value(&global(0x32000, 2, "Word")), "$FFFE (-2)");
+        assert_eq!(format_value(&global(0x32000, 6, "Bytes")), "FF FE 00 00 12 34");
+        assert_eq!(format_value(&global(0x32010, 16, "SysResName")), "\"Hello\"");
+        assert_eq!(format_value(&global(0x32020, 4, "Ticks")), "256 (0x00000100)");
+    }
+}
@@ -0,0 +1,139 @@
+/* MacsBug symbol extraction.
+ *
+ * Compilers place a routine's name right after its final RTS, RTD or
+ * JMP (A0), in one of three encodings:
+ *
+ *   variable length   $80+len (len 1..31) then the name, or $80, len, name;
+ *                     padded to a word, followed by a word of constant-data
+ *                     length and that much data
+ *   fixed 8           8 characters, the first with bit 7 set
+ *   fixed 16          16 characters, the first two with bit 7 set
+ *
+ * The routine is taken to start at the first LINK A6 after the previous
+ * routine's name, or right after that name if there is no LINK.
+ */
+
+use crate::memory::{peek_u16, peek_u32, peek_u8, RAM_SIZE};
+use crate::symbols;
+
+const RTS: u16 = 0x4E75;
+const RTD: u16 = 0x4E74;
+const JMP_A0: u16 = 0x4ED0;
+const LINK_A6: u16 = 0x4E56;
+
+// Low-memory global holding the top of RAM
+const MEM_TOP: u32 = 0x108;
+
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'%' | b'.')
+}
+
+/// Decodes a MacsBug name at `addr`, returning it and the address just past it.
+pub fn name_at(addr: u32) -> Option<(String, u32)> {
+    let first = peek_u8(addr);
+    if first & 0x80 == 0 {
+        return None;
+    }
+    if (0x80..0xA0).contains(&first) {
+        // Variable length
+        let (len, start) = if first == 0x80 {
+            (peek_u8(addr + 1) as u32, addr + 2)
+        } else {
+            ((first & 0x7F) as u32, addr + 1)
+        };
+        if len == 0 {
+            return None;
+        }
+        let bytes: Vec<u8> = (0..len).map(|i| peek_u8(start + i)).collect();
+        if !bytes.iter().all(|&c| is_name_char(c)) {
+            return None;
+        }
+        let end = (start + len + 1) & !1;
+        let constants = peek_u16(end) as u32;
+        return Some((String::from_utf8_lossy(&bytes).into_owned(), end + 2 + ((constants + 1) & !1)));
+    }
+    // Fixed 8 or 16; trailing spaces pad short names
+    let len = if peek_u8(addr + 1) & 0x80 != 0 { 16 } else { 8 };
+    let bytes: Vec<u8> = (0..len).map(|i| peek_u8(addr + i) & 0x7F).collect();
+    let name = String::from_utf8_lossy(&bytes).trim_end().to_string();
+    if name.is_empty() || !name.bytes().all(is_name_char) {
+        return None;
+    }
+    Some((name, addr + len))
+}
+
+/// Scans `[start, end)` for MacsBug names, returning (routine start, name) pairs.
+pub fn scan(start: u32, end: u32) -> Vec<(u32, String)> {
+    let mut found = Vec::new();
+    let mut routine_start = start & !1;
+    let mut link: Option<u32> = None;
+    let mut addr = routine_start;
+    while addr + 2 < end {
+        let word = peek_u16(addr);
+        if word == LINK_A6 && link.is_none() {
+            link = Some(addr);
+        }
+        if matches!(word, RTS | RTD | JMP_A0) {
+            // RTD carries a displacement word
+            let name_addr = if word == RTD { addr + 4 } else { addr + 2 };
+            if let Some((name, next)) = name_at(name_addr) {
+                found.push((link.unwrap_or(routine_start), name));
+                routine_start = (next + 1) & !1;
+                link = None;
+                addr = routine_start;
+                continue;
+            }
+        }
+        addr += 2;
+    }
+    found
+}
+
+/// Scans RAM up to MemTop (or `[start, end)`) and adds every name found to the symbol table.
+pub fn load_symbols(range: Option<(u32, u32)>) -> usize {
+    let (start, end) = range.unwrap_or_else(|| {
+        let top = peek_u32(MEM_TOP);
+        (0, if top == 0 || top > RAM_SIZE as u32 { RAM_SIZE as u32 } else { top })
+    });
+    let found = scan(start, end);
+    for (addr, name) in &found {
+        symbols::add(*addr, name);
+    }
+    found.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn name_at_variable_length() {
+        put(0x30000, b"\x86DoDraw\x00\x00\x04\x01\x02\x03\x04");
+        assert_eq!(name_at(0x30000), Some(("DoDraw".to_string(), 0x30000 + 14)));
+        put(0x30100, b"\x80\x05Paint\x00\x00");
+        assert_eq!(name_at(0x30100), Some(("Paint".to_string(), 0x30100 + 10)));
+    }
+
+    #[test]
+    fn name_at_fixed_length() {
+        put(0x30200, b"\xC9NIT    ");
+        assert_eq!(name_at(0x30200), Some(("INIT".to_string(), 0x30208)));
+        put(0x30300, b"\xC4\xD2AWWINDOWFRAME ");
+        assert_eq!(name_at(0x30300), Some(("DRAWWINDOWFRAME".to_string(), 0x30310)));
+    }
+
+    #[test]
+    fn name_at_rejects_code() {
+        put(0x30400, &[0x4E, 0x75]);
+        assert_eq!(name_at(0x30400), None);
+        put(0x30500, b"\x84a b!");
+        assert_eq!(name_at(0x30500), None);
+    }
+}
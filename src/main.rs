@@ -1,11 +1,7 @@
-mod cpu;
-mod memory;
-mod video;
-mod via;
-mod iwm;
+use mac128k_emulator::{cpu, memory, video, via, watchpoint, debugger, gdbstub, traps, calltrace, profiler, coverage, symbols, crash, events, watchdog, patches, segments, memsearch, shadow, disasm, shutdown, set_exit_dump};
 use via::{Via, ViaCallbacks, set_via};
 
-use cpu::{init, step, get_pc, display_registers};
+use cpu::{init, step, get_pc, set_pc, display_registers, CLOCK_HZ};
 use memory::{write_u16, write_u32, RAM_SIZE, load_rom};
 use video::MacVideo;
 use std::time::{Duration, Instant};
@@ -26,6 +22,134 @@ fn wait_for_keypress() {
 
 fn dummy_irq_set(_irq: bool) {}
 
+/// Splits `FILE@ADDR[+LEN]`.
+fn split_at_address(spec: &str) -> Option<(&str, u32, Option<u32>)> {
+    let (path, location) = spec.rsplit_once('@')?;
+    let (addr, len) = match location.split_once('+') {
+        Some((addr, len)) => (addr, Some(memory::parse_address(len)?)),
+        None => (location, None),
+    };
+    Some((path, symbols::parse_symbolic(addr)?, len))
+}
+
+/// Brings up the memory map and devices once the CPU has read its reset vectors.
+fn power_on() {
+    // TODO: ugly hack for now...should be done with VIA chip output (I think)
+    // Remap ROM so RAM is available at 0x0
+    memory::remap_rom();
+    info!("ROM remapped - RAM now available at 0x0");
+    patches::reset();
+    crash::reset();
+
+    // TODO: we may need interrupts and SCC chip implementation
+
+    // Initialize VIA
+    let via = Via::new(ViaCallbacks {
+        ra_change: None,
+        rb_change: None,
+        ra_in: None,
+        rb_in: None,
+        sr_tx: None,
+        irq_set: dummy_irq_set,
+    });
+    set_via(via);
+}
+
+/// `disasm ROM OUTPUT [--symbols FILE]... [--boot SECONDS]`: writes a static listing of the ROM,
+/// seeded from the reset vector and the dispatch table in the ROM image.  With `--boot` the ROM
+/// is also run headless for that many emulated seconds and the exception vectors and trap
+/// dispatch table it installs are added.
+fn disasm_command(args: &[String]) {
+    let usage = format!("Usage: {} disasm ROM OUTPUT [--symbols FILE]... [--boot SECONDS]", args[0]);
+    let (Some(rom), Some(output)) = (args.get(2), args.get(3)) else {
+        error!("{}", usage);
+        return;
+    };
+    let mut boot_seconds = 0.0;
+    let mut symbol_files = Vec::new();
+    let mut opts = args[4..].iter();
+    while let Some(opt) = opts.next() {
+        match (opt.as_str(), opts.next()) {
+            ("--symbols", Some(path)) => symbol_files.push(path),
+            ("--boot", Some(seconds)) => match seconds.parse() {
+                Ok(seconds) => boot_seconds = seconds,
+                Err(_) => {
+                    error!("--boot expects a number of emulated seconds");
+                    return;
+                }
+            },
+            _ => {
+                error!("{}", usage);
+                return;
+            }
+        }
+    }
+
+    if let Err(e) = memory::load_rom(rom) {
+        error!("Error loading ROM: {}", e);
+        return;
+    }
+    symbols::load_rom_labels();
+    for path in symbol_files {
+        if let Err(e) = symbols::load(path) {
+            error!("{}", e);
+            return;
+        }
+    }
+
+    let mut entries = disasm::image_entries();
+    if boot_seconds > 0.0 {
+        debugger::set_interactive(false);
+        crash::set_break_on_exception(false);
+        init();
+        power_on();
+        let limit = (boot_seconds * CLOCK_HZ as f64) as u64;
+        let mut cycles: u64 = 0;
+        while cycles < limit {
+            let executed = step(CYCLES_PER_BATCH);
+            if executed <= 0 || !events::take_events().is_empty() {
+                break;
+            }
+            cycles += executed as u64;
+        }
+        entries.extend(disasm::installed_entries());
+    }
+    info!("Disassembling from {} entry points", entries.len());
+
+    let listing = disasm::trace(&entries);
+    match listing.write(output) {
+        Ok(()) => info!("Wrote {} ({} bytes of code)", output, listing.code_bytes()),
+        Err(e) => error!("Failed to write {}: {}", output, e),
+    }
+}
+
+/// Runs without a window until the guest crashes or `run_for` emulated seconds
+/// have passed, exiting with the crash event's status (0 if none).
+fn run_headless(run_for: Option<f64>) -> ! {
+    let limit = run_for.map(|seconds| (seconds * CLOCK_HZ as f64) as u64);
+    // One parseable line per event on stdout for test harnesses
+    events::add_handler(|event| println!("EVENT {:?}", event));
+    let mut cycles: u64 = 0;
+    loop {
+        gdbstub::poll();
+        memsearch::apply_freezes();
+        if debugger::is_paused() {
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        cycles += step(CYCLES_PER_BATCH).max(0) as u64;
+        if let Some(event) = events::take_events().into_iter().next() {
+            shutdown();
+            std::process::exit(event.exit_code());
+        }
+        if limit.is_some_and(|limit| cycles >= limit) {
+            info!("Ran for {} emulated seconds without a crash", run_for.unwrap_or_default());
+            shutdown();
+            std::process::exit(0);
+        }
+    }
+}
+
 //#[tokio::main]
 fn main() {
     // Set default log level if not specified
@@ -35,16 +159,180 @@ fn main() {
     env_logger::init();
 
     let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        disasm_command(&args);
+        return;
+    }
     if args.len() < 2 {
-        error!("Usage: {} [path_to_rom]", args[0]);
+        error!("Usage: {} [path_to_rom] [--watch [r|w|c]:ADDR[+LEN]]... [--symbols FILE]... [--gdb PORT] [--trap-log] [--fs-trace] [--seg-trace] [--shadow] [--load FILE@ADDR]... [--entry ADDR] [--dump FILE[@ADDR[+LEN]]] [--profile FILE] [--coverage FILE] [--crash-report FILE] [--headless] [--run-for SECONDS] [--sad-mac ADDR] [--watchdog SECONDS] [--watchdog-snapshot FILE]", args[0]);
+        error!("   or: {} disasm ROM OUTPUT [--symbols FILE]... [--boot SECONDS]", args[0]);
         return;
     }
 
+    let mut gdb_port: Option<u16> = None;
+    let mut headless = false;
+    let mut run_for: Option<f64> = None;
+    let mut sad_mac: Option<&String> = None;
+    let mut watch_specs = Vec::new();
+    let mut symbol_files = Vec::new();
+    let mut load_specs = Vec::new();
+    let mut entry: Option<&String> = None;
+    let mut opts = args[2..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            // Parsed once symbols are loaded so specs can name addresses
+            "--watch" => match opts.next() {
+                Some(spec) => watch_specs.push(spec),
+                None => {
+                    error!("--watch expects [r|w|c]:ADDR[+LEN]");
+                    return;
+                }
+            },
+            "--load" => match opts.next() {
+                Some(spec) => load_specs.push(spec),
+                None => {
+                    error!("--load expects FILE@ADDR");
+                    return;
+                }
+            },
+            "--entry" => match opts.next() {
+                Some(addr) => entry = Some(addr),
+                None => {
+                    error!("--entry expects an address");
+                    return;
+                }
+            },
+            "--dump" => match opts.next() {
+                Some(spec) if spec.contains('@') => match split_at_address(spec) {
+                    Some((path, addr, len)) => set_exit_dump(path, addr, len),
+                    None => {
+                        error!("--dump expects FILE[@ADDR[+LEN]], got '{}'", spec);
+                        return;
+                    }
+                },
+                Some(path) => set_exit_dump(path, 0, None),
+                None => {
+                    error!("--dump expects FILE[@ADDR[+LEN]]");
+                    return;
+                }
+            },
+            "--headless" => headless = true,
+            "--run-for" => match opts.next().and_then(|s| s.parse().ok()) {
+                Some(seconds) => run_for = Some(seconds),
+                None => {
+                    error!("--run-for expects a number of emulated seconds");
+                    return;
+                }
+            },
+            "--sad-mac" => match opts.next() {
+                Some(addr) => sad_mac = Some(addr),
+                None => {
+                    error!("--sad-mac expects the address of the ROM's Sad Mac routine");
+                    return;
+                }
+            },
+            "--watchdog" => match opts.next().and_then(|s| s.parse().ok()) {
+                Some(seconds) => watchdog::start(seconds),
+                None => {
+                    error!("--watchdog expects a number of emulated seconds");
+                    return;
+                }
+            },
+            "--watchdog-snapshot" => match opts.next() {
+                Some(path) => watchdog::set_snapshot(path),
+                None => {
+                    error!("--watchdog-snapshot expects an output file");
+                    return;
+                }
+            },
+            "--crash-report" => match opts.next() {
+                Some(path) => crash::set_report_path(path),
+                None => {
+                    error!("--crash-report expects an output file");
+                    return;
+                }
+            },
+            "--symbols" => match opts.next() {
+                Some(path) => symbol_files.push(path),
+                None => {
+                    error!("--symbols expects a symbol file");
+                    return;
+                }
+            },
+            "--gdb" => match opts.next().and_then(|port| port.parse().ok()) {
+                Some(port) => gdb_port = Some(port),
+                None => {
+                    error!("--gdb expects a TCP port number");
+                    return;
+                }
+            },
+            "--trap-log" => traps::set_logging(true),
+            "--fs-trace" => calltrace::set_enabled(true),
+            "--seg-trace" => segments::set_logging(true),
+            "--shadow" => shadow::start(true),
+            "--profile" => match opts.next() {
+                Some(path) => {
+                    profiler::set_output(path);
+                    profiler::start();
+                }
+                None => {
+                    error!("--profile expects an output file");
+                    return;
+                }
+            },
+            "--coverage" => match opts.next() {
+                Some(path) => {
+                    coverage::set_output(path);
+                    coverage::start();
+                }
+                None => {
+                    error!("--coverage expects an output file");
+                    return;
+                }
+            },
+            _ => {
+                error!("Unknown option: {}", opt);
+                return;
+            }
+        }
+    }
+
     if let Err(e) = memory::load_rom(&args[1]) {
         error!("Error loading ROM: {}", e);
         return;
     }
 
+    symbols::load_rom_labels();
+    for path in symbol_files {
+        if let Err(e) = symbols::load(path) {
+            error!("{}", e);
+            return;
+        }
+    }
+    let sad_mac = match sad_mac {
+        Some(addr) => match symbols::parse_symbolic(addr) {
+            Some(addr) => Some(addr),
+            None => {
+                error!("--sad-mac: bad address '{}'", addr);
+                return;
+            }
+        },
+        None => None,
+    };
+    events::init(sad_mac);
+    for spec in watch_specs {
+        match watchpoint::parse_spec(spec) {
+            Some((addr, len, kind)) => {
+                watchpoint::add(addr, len, kind);
+                info!("Watching 0x{:06X}+{} for {}", addr, len, kind);
+            }
+            None => {
+                error!("--watch expects [r|w|c]:ADDR[+LEN], got '{}'", spec);
+                return;
+            }
+        }
+    }
+
     // Initialize test pattern in video memory
     for y in 0..342 {
         for x in 0..64 {
@@ -57,33 +345,84 @@ fn main() {
 
     // Initialize CPU (will read vectors from 0x000000 and 0x000004)
     init();
-    wait_for_keypress();
+    if !headless {
+        wait_for_keypress();
+    }
 
-    // TODO: ugly hack for now...should be done with VIA chip output (I think)
-    // Remap ROM so RAM is available at 0x0
-    memory::remap_rom();
-    info!("ROM remapped - RAM now available at 0x0");
+    power_on();
 
-    // TODO: we may need interrupts and SCC chip implementation
+    // Raw binaries go in once RAM is mapped at 0
+    for spec in load_specs {
+        // The file's own size is the length; a +LEN would be silently ignored
+        let Some((path, addr, None)) = split_at_address(spec) else {
+            error!("--load expects FILE@ADDR, got '{}'", spec);
+            return;
+        };
+        match memory::load_memory(path, addr) {
+            Ok(len) => info!("Loaded {} ({} bytes) at 0x{:06X}", path, len, addr),
+            Err(e) => {
+                error!("Failed to load {}: {}", path, e);
+                return;
+            }
+        }
+    }
+    if let Some(addr) = entry {
+        match symbols::parse_symbolic(addr) {
+            Some(addr) => {
+                set_pc(addr);
+                info!("PC set to 0x{:06X}", addr);
+            }
+            None => {
+                error!("--entry: bad address '{}'", addr);
+                return;
+            }
+        }
+    }
+
+    if headless {
+        // With no window and no console only a GDB client can resume a stopped machine
+        debugger::set_interactive(gdb_port.is_some());
+    } else {
+        debugger::start_console();
+    }
+    if let Some(port) = gdb_port {
+        if let Err(e) = gdbstub::start(port) {
+            error!("Failed to start GDB stub on port {}: {}", port, e);
+            return;
+        }
+    }
+    if headless {
+        run_headless(run_for);
+    }
+    info!("Debugger console ready: press F12 or type 'stop' to break in, 'help' for commands");
 
     // Initialize video
     let (video, event_loop) = MacVideo::new();
-    
-    // Initialize VIA
-    let via = Via::new(ViaCallbacks {
-        ra_change: None,
-        rb_change: None,
-        ra_in: None,
-        rb_in: None,
-        sr_tx: None,
-        irq_set: dummy_irq_set,
-    });
-    set_via(via);
 
     // Run the video event loop, which calls the CPU execution step
     video.run(event_loop, || {
-        let _ = step(CYCLES_PER_BATCH);
+        debugger::poll();
+        gdbstub::poll();
+        memsearch::apply_freezes();
+        if !debugger::is_paused() {
+            let _ = step(CYCLES_PER_BATCH);
+        }
         //display_registers();
         //wait_for_keypress();
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_at_address_forms() {
+        assert_eq!(split_at_address("app.bin@0x2000"), Some(("app.bin", 0x2000, None)));
+        assert_eq!(split_at_address("ram.bin@$0+0x400"), Some(("ram.bin", 0, Some(0x400))));
+        // The last @ separates the address, so paths may contain one
+        assert_eq!(split_at_address("a@b.bin@4096"), Some(("a@b.bin", 4096, None)));
+        assert_eq!(split_at_address("app.bin"), None);
+        assert_eq!(split_at_address("app.bin@0x2000+"), None);
+    }
+}
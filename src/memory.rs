@@ -1,26 +1,24 @@
 use std::fs;
 use log::{info, warn};
 use crate::via::{VIA};
-use std::io;
-use std::io::Write;
-use crate::cpu::{get_pc, disassemble_instruction};
+use crate::debugger;
+use crate::shadow;
 use crate::iwm::Iwm;
 use lazy_static::lazy_static;
 use std::sync::Mutex;
-use std::sync::atomic::{AtomicBool, Ordering};
 
 lazy_static! {
     static ref IWM_INSTANCE: Mutex<Iwm> = Mutex::new(Iwm::new());
 }
 
-pub(crate) static SINGLE_STEP: AtomicBool = AtomicBool::new(false);
-
 // TODO: mac plus rom maps over our VIDEO_BASE
 pub const RAM_SIZE: usize = 0x1000000;
 pub const ROM_SIZE: usize = 0x10000;
 pub const VIDEO_BASE: usize = 0x1A700;
 pub const ROM_BASE: u32 = 0x400000;
 pub const ROM_END: u32 = ROM_BASE + (ROM_SIZE as u32) - 1;
+// Low-memory global holding the end of installed RAM
+const MEM_TOP: u32 = 0x108;
 
 pub static mut RAM: [u8; RAM_SIZE] = [0; RAM_SIZE];
 pub static mut ROM: [u8; ROM_SIZE] = [0; ROM_SIZE];
@@ -47,50 +45,23 @@ pub fn remap_rom() {
     }
 }
 
-pub(crate) fn wait_for_keypress_hw(label: &str, addr: u32) -> bool {
-    let pc = get_pc();
-    let disasm = disassemble_instruction(pc);
-    crate::cpu::display_registers();
-    println!("{} at 0x{:X}\n  PC: 0x{:08X}  {}\nPress Enter to continue, or 's' then Enter to single-step...", label, addr, pc, disasm);
-    io::stdout().flush().unwrap();
-    let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
-    if input.trim() == "s" {
-        SINGLE_STEP.store(true, Ordering::SeqCst);
-        false
-    } else {
-        SINGLE_STEP.store(false, Ordering::SeqCst);
-        true
-    }
-}
-
 pub fn read_u8(addr: u32) -> u8 {
     // IWM: ((addr & 0xFFFFFF) >= 0xDFE1FF) && ((addr & 0xFFFFFF) < 0xE001FF)
     if (addr & 0xFFFFFF) >= 0xDFE1FF && (addr & 0xFFFFFF) < (0xDFE1FF + 0x2000) {
         log::warn!("IWM hardware read at 0x{:X}", addr);
-        let cont = wait_for_keypress_hw("IWM hardware read", addr);
-        if !cont {
-            // single-step mode: pause after this instruction
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("IWM hardware read at 0x{:X}", addr));
         let iwm = IWM_INSTANCE.lock().unwrap();
         return iwm.read(addr);
     }
     // SCC_RD: ((addr & 0xF00000) == 0x900000)
     if (addr & 0xF00000) == 0x900000 {
         log::warn!("SCC_RD hardware read at 0x{:X}", addr);
-        let cont = wait_for_keypress_hw("SCC_RD hardware read", addr);
-        if !cont {
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("SCC_RD hardware read at 0x{:X}", addr));
     }
     // SCC_WR: ((addr & 0xF00000) == 0xB00000)
     if (addr & 0xF00000) == 0xB00000 {
         log::warn!("SCC_WR hardware read at 0x{:X}", addr);
-        let cont = wait_for_keypress_hw("SCC_WR hardware read", addr);
-        if !cont {
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("SCC_WR hardware read at 0x{:X}", addr));
     }
     if (addr & 0xE80000) == 0xE80000 {
         let mut via_lock = VIA.lock().unwrap();
@@ -119,10 +90,7 @@ pub fn write_u8(addr: u32, value: u8) {
     // IWM: ((addr & 0xFFFFFF) >= 0xDFE1FF) && ((addr & 0xFFFFFF) < 0xE001FF)
     if (addr & 0xFFFFFF) >= 0xDFE1FF && (addr & 0xFFFFFF) < (0xDFE1FF + 0x2000) {
         log::warn!("IWM hardware write at 0x{:X} = 0x{:X}", addr, value);
-        let cont = wait_for_keypress_hw("IWM hardware write", addr);
-        if !cont {
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("IWM hardware write at 0x{:X}", addr));
         let mut iwm = IWM_INSTANCE.lock().unwrap();
         iwm.write(addr, value);
         return;
@@ -130,18 +98,12 @@ pub fn write_u8(addr: u32, value: u8) {
     // SCC_RD: ((addr & 0xF00000) == 0x900000)
     if (addr & 0xF00000) == 0x900000 {
         log::warn!("SCC_RD hardware write at 0x{:X} = 0x{:X}", addr, value);
-        let cont = wait_for_keypress_hw("SCC_RD hardware write", addr);
-        if !cont {
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("SCC_RD hardware write at 0x{:X}", addr));
     }
     // SCC_WR: ((addr & 0xF00000) == 0xB00000)
     if (addr & 0xF00000) == 0xB00000 {
         log::warn!("SCC_WR hardware write at 0x{:X} = 0x{:X}", addr, value);
-        let cont = wait_for_keypress_hw("SCC_WR hardware write", addr);
-        if !cont {
-            SINGLE_STEP.store(true, Ordering::SeqCst);
-        }
+        debugger::request_break(&format!("SCC_WR hardware write at 0x{:X}", addr));
     }
     if (addr & 0xE80000) == 0xE80000 {
         let mut via_lock = VIA.lock().unwrap();
@@ -154,10 +116,10 @@ pub fn write_u8(addr: u32, value: u8) {
     }
     unsafe {
         if ROM_MAPPED_AT_ZERO && addr < ROM_SIZE as u32 {
-            let _ = wait_for_keypress_hw("write_u8 attempt to write to ROM@0", addr);
+            debugger::request_break(&format!("Write to ROM@0 at 0x{:X}", addr));
             warn!("write_u8 attempt to write to ROM@0: 0x{:X}", addr);
         } else if addr >= ROM_BASE && addr < ROM_BASE + ROM_SIZE as u32 {
-            let _ = wait_for_keypress_hw("write_u8 attempt to write to ROM@400000", addr);
+            debugger::request_break(&format!("Write to ROM@400000 at 0x{:X}", addr));
             warn!("write_u8 attempt to write to ROM@400000: 0x{:X}", addr);
         } else if addr < RAM_SIZE as u32 {
             info!("write_u8 (RAM): 0x{:X} = 0x{:X}", addr, value);
@@ -168,6 +130,130 @@ pub fn write_u8(addr: u32, value: u8) {
     }
 }
 
+/// Writes `len` bytes starting at `start` to a host file, reading as the debugger does.
+pub fn save_memory(path: &str, start: u32, len: u32) -> std::io::Result<()> {
+    let bytes: Vec<u8> = (0..len).map(|i| peek_u8(start.wrapping_add(i))).collect();
+    fs::write(path, bytes)
+}
+
+/// Writes installed RAM to a host file straight from the RAM array, so the
+/// ROM overlay at 0 doesn't hide the vectors; returns the length written.
+pub fn save_ram(path: &str) -> std::io::Result<u32> {
+    let len = ram_end();
+    let bytes: Vec<u8> = (0..len as usize).map(|i| unsafe { RAM[i] }).collect();
+    fs::write(path, bytes)?;
+    Ok(len)
+}
+
+/// Copies a host file into RAM at `addr`, returning its length.
+pub fn load_memory(path: &str, addr: u32) -> std::io::Result<u32> {
+    let bytes = fs::read(path)?;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let target = addr.wrapping_add(i as u32);
+        if !poke_u8(target, byte) {
+            let message = format!("0x{:06X} is not RAM", target);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+        }
+    }
+    Ok(bytes.len() as u32)
+}
+
+/// End of installed RAM, from MemTop once the ROM has set it.
+pub fn ram_end() -> u32 {
+    let top = peek_u32(MEM_TOP);
+    if top == 0 || top > RAM_SIZE as u32 { RAM_SIZE as u32 } else { top }
+}
+
+/// Offset into the ROM image if `addr` currently maps to ROM.
+pub fn rom_offset(addr: u32) -> Option<u32> {
+    unsafe {
+        if ROM_MAPPED_AT_ZERO && addr < ROM_SIZE as u32 {
+            Some(addr)
+        } else if addr >= ROM_BASE && addr < ROM_BASE + ROM_SIZE as u32 {
+            Some(addr - ROM_BASE)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads RAM or ROM without touching I/O devices; hardware ranges read as 0xFF.
+pub fn peek_u8(addr: u32) -> u8 {
+    if (addr & 0xFFFFFF) >= 0xDFE1FF && (addr & 0xFFFFFF) < (0xDFE1FF + 0x2000) {
+        return 0xFF;
+    }
+    if (addr & 0xF00000) == 0x900000 || (addr & 0xF00000) == 0xB00000 || (addr & 0xE80000) == 0xE80000 {
+        return 0xFF;
+    }
+    unsafe {
+        if ROM_MAPPED_AT_ZERO && addr < ROM_SIZE as u32 {
+            ROM[addr as usize]
+        } else if addr >= ROM_BASE && addr < ROM_BASE + ROM_SIZE as u32 {
+            ROM[(addr - ROM_BASE) as usize]
+        } else if addr < RAM_SIZE as u32 {
+            RAM[addr as usize]
+        } else {
+            0xFF
+        }
+    }
+}
+
+pub fn peek_u16(addr: u32) -> u16 {
+    ((peek_u8(addr) as u16) << 8) | peek_u8(addr.wrapping_add(1)) as u16
+}
+
+pub fn peek_u32(addr: u32) -> u32 {
+    ((peek_u16(addr) as u32) << 16) | peek_u16(addr.wrapping_add(2)) as u32
+}
+
+/// Reads a Pascal string (length byte followed by text).
+pub fn peek_pstring(addr: u32) -> String {
+    let len = peek_u8(addr) as u32;
+    (1..=len).map(|i| mac_char(peek_u8(addr.wrapping_add(i)))).collect()
+}
+
+/// Formats a four-character code such as a ResType or file type.
+pub fn ostype(value: u32) -> String {
+    value.to_be_bytes().iter().map(|&b| mac_char(b)).collect()
+}
+
+fn mac_char(b: u8) -> char {
+    if (0x20..0x7F).contains(&b) { b as char } else { '.' }
+}
+
+/// Whether `addr` currently maps to RAM rather than ROM or nothing.
+pub fn is_ram(addr: u32) -> bool {
+    rom_offset(addr).is_none() && addr < RAM_SIZE as u32
+}
+
+/// Writes RAM directly, bypassing I/O and ROM; returns false if `addr` isn't RAM.
+pub fn poke_u8(addr: u32, value: u8) -> bool {
+    if !is_ram(addr) {
+        return false;
+    }
+    if shadow::enabled() {
+        shadow::on_write(addr, 1);
+    }
+    unsafe {
+        RAM[addr as usize] = value;
+    }
+    true
+}
+
+pub fn iwm_state() -> String {
+    IWM_INSTANCE.lock().unwrap().dump()
+}
+
+/// Parses `0x1234`, `$1234` or plain decimal.
+pub fn parse_address(s: &str) -> Option<u32> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).or_else(|| s.strip_prefix('$')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
 pub fn read_u16(addr: u32) -> u16 {
     //info!("read_u16: 0x{:X}", addr);
     let high = read_u8(addr) as u16;
@@ -194,15 +280,30 @@ pub fn write_u32(addr: u32, value: u32) {
 
 #[no_mangle]
 pub extern "C" fn m68k_read_disassembler_8(address: u32) -> u32 {
-    read_u8(address) as u32
+    peek_u8(address) as u32
 }
 
 #[no_mangle]
 pub extern "C" fn m68k_read_disassembler_16(address: u32) -> u32 {
-    read_u16(address) as u32
+    peek_u16(address) as u32
 }
 
 #[no_mangle]
 pub extern "C" fn m68k_read_disassembler_32(address: u32) -> u32 {
-    read_u32(address)
+    peek_u32(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address_formats() {
+        assert_eq!(parse_address("0x16A"), Some(0x16A));
+        assert_eq!(parse_address("0X16a"), Some(0x16A));
+        assert_eq!(parse_address("$400000"), Some(0x400000));
+        assert_eq!(parse_address(" 42 "), Some(42));
+        assert_eq!(parse_address("0xZZ"), None);
+        assert_eq!(parse_address(""), None);
+    }
 }
@@ -0,0 +1,198 @@
+/* RAM search, freeze and poke.
+ *
+ * A search keeps its hits as candidates along with the value each had, so
+ * later passes can narrow them to those that changed, stayed the same,
+ * went up or down, or now equal a given value.  Starting from every
+ * aligned address instead of a known value finds state whose value isn't
+ * known.  Frozen values are written back every frame.
+ */
+
+use crate::memory::{is_ram, peek_u8, poke_u8, ram_end};
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref CANDIDATES: Mutex<Vec<Candidate>> = Mutex::new(Vec::new());
+    static ref FREEZES: Mutex<Vec<Freeze>> = Mutex::new(Vec::new());
+}
+
+// Set while any value is frozen, so frames without freezes skip the lock
+static FREEZING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+pub struct Candidate {
+    pub addr: u32,
+    pub size: u32,
+    pub value: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Freeze {
+    pub addr: u32,
+    pub size: u32,
+    pub value: u32,
+}
+
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(u32),
+}
+
+/// Whether `value` fits in `size` (1, 2 or 4) bytes.
+pub fn fits(value: u32, size: u32) -> bool {
+    size >= 4 || value >> (8 * size) == 0
+}
+
+/// Reads a big-endian value of 1, 2 or 4 bytes.
+pub fn read(addr: u32, size: u32) -> u32 {
+    (0..size).fold(0, |acc, i| (acc << 8) | peek_u8(addr.wrapping_add(i)) as u32)
+}
+
+/// Writes a big-endian value of 1, 2 or 4 bytes to RAM; false, writing nothing, if any byte isn't RAM.
+pub fn write(addr: u32, size: u32, value: u32) -> bool {
+    if !(0..size).all(|i| is_ram(addr.wrapping_add(i))) {
+        return false;
+    }
+    (0..size).all(|i| poke_u8(addr.wrapping_add(i), (value >> (8 * (size - 1 - i))) as u8))
+}
+
+/// Addresses in RAM where `pattern` occurs.
+pub fn find_bytes(pattern: &[u8]) -> Vec<u32> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    let end = ram_end();
+    let ram: Vec<u8> = (0..end).map(peek_u8).collect();
+    ram.windows(pattern.len()).enumerate().filter(|(_, w)| *w == pattern).map(|(i, _)| i as u32).collect()
+}
+
+fn start(addrs: impl Iterator<Item = u32>, size: u32) -> usize {
+    let hits: Vec<Candidate> = addrs.map(|addr| Candidate { addr, size, value: read(addr, size) }).collect();
+    let count = hits.len();
+    *CANDIDATES.lock().unwrap() = hits;
+    count
+}
+
+/// Starts a new search: every place `pattern` occurs, tracking the byte there.
+pub fn search_bytes(pattern: &[u8]) -> usize {
+    start(find_bytes(pattern).into_iter(), 1)
+}
+
+/// Starts a search for a 1, 2 or 4-byte value; words and longs must be even-aligned.
+pub fn search_value(value: u32, size: u32) -> usize {
+    let bytes = value.to_be_bytes();
+    let hits = find_bytes(&bytes[4 - size as usize..]).into_iter().filter(|addr| size == 1 || addr & 1 == 0);
+    start(hits, size)
+}
+
+/// Starts an unknown-value search over every aligned address in RAM.
+pub fn search_all(size: u32) -> usize {
+    let step = if size == 1 { 1 } else { 2 };
+    start((0..ram_end() - size + 1).step_by(step), size)
+}
+
+/// Keeps the candidates matching `filter` and records their current values.
+pub fn narrow(filter: Filter) -> usize {
+    let mut candidates = CANDIDATES.lock().unwrap();
+    candidates.retain_mut(|c| {
+        let now = read(c.addr, c.size);
+        let keep = match filter {
+            Filter::Changed => now != c.value,
+            Filter::Unchanged => now == c.value,
+            Filter::Increased => now > c.value,
+            Filter::Decreased => now < c.value,
+            Filter::Equals(value) => now == value,
+        };
+        c.value = now;
+        keep
+    });
+    candidates.len()
+}
+
+pub fn candidates() -> Vec<Candidate> {
+    CANDIDATES.lock().unwrap().clone()
+}
+
+/// Pins `size` bytes at `addr` to `value`.
+pub fn freeze(addr: u32, size: u32, value: u32) {
+    let mut freezes = FREEZES.lock().unwrap();
+    freezes.retain(|f| f.addr != addr);
+    freezes.push(Freeze { addr, size, value });
+    FREEZING.store(true, Ordering::Relaxed);
+}
+
+/// Removes freeze `index` (0-based); false if there is none.
+pub fn unfreeze(index: usize) -> bool {
+    let mut freezes = FREEZES.lock().unwrap();
+    let removed = index < freezes.len();
+    if removed {
+        freezes.remove(index);
+    }
+    FREEZING.store(!freezes.is_empty(), Ordering::Relaxed);
+    removed
+}
+
+pub fn unfreeze_all() {
+    FREEZES.lock().unwrap().clear();
+    FREEZING.store(false, Ordering::Relaxed);
+}
+
+pub fn freezes() -> Vec<Freeze> {
+    FREEZES.lock().unwrap().clone()
+}
+
+/// Rewrites every frozen value; called once per frame.
+pub fn apply_freezes() {
+    if !FREEZING.load(Ordering::Relaxed) {
+        return;
+    }
+    for f in FREEZES.lock().unwrap().iter() {
+        write(f.addr, f.size, f.value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_filters_and_tracks_values() {
+        for (addr, value) in [(0x31000, 100), (0x31002, 200), (0x31004, 300)] {
+            assert!(write(addr, 2, value));
+        }
+        assert_eq!(start([0x31000, 0x31002, 0x31004].into_iter(), 2), 3);
+        write(0x31000, 2, 101);
+        write(0x31004, 2, 299);
+        assert_eq!(narrow(Filter::Changed), 2);
+        assert_eq!(narrow(Filter::Unchanged), 2);
+        write(0x31000, 2, 150);
+        write(0x31004, 2, 250);
+        assert_eq!(narrow(Filter::Increased), 1);
+        let left = candidates();
+        assert_eq!((left[0].addr, left[0].value), (0x31000, 150));
+        assert_eq!(narrow(Filter::Equals(150)), 1);
+        assert_eq!(narrow(Filter::Decreased), 0);
+    }
+
+    #[test]
+    fn write_is_all_or_nothing() {
+        // The last RAM word before the ROM
+        assert!(write(0x3FFFFE, 1, 0x5A));
+        assert!(!write(0x3FFFFE, 4, 0x12345678));
+        assert_eq!(read(0x3FFFFE, 2), 0x5A00);
+        assert!(!write(0xFFFFFFFF, 2, 0x1234));
+    }
+
+    #[test]
+    fn values_must_fit_the_size() {
+        assert!(fits(0xFF, 1));
+        assert!(!fits(0x100, 1));
+        assert!(!fits(0x12345, 2));
+        assert!(fits(0xFFFFFFFF, 4));
+    }
+}
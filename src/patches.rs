@@ -0,0 +1,114 @@
+/* Trap patch detection.
+ *
+ * The 64K ROM's trap dispatch table holds 512 word entries at $400: the
+ * routine's offset from ROMBase divided by two, or, with bit 15 set, the
+ * address of a patch in RAM divided by two.  The whole table is copied the
+ * first time a trap executes, when the ROM's dispatcher init has filled it
+ * in, and kept as the originals, so a patched trap can be listed with both
+ * addresses and with the heap block, and resource if any, that holds the
+ * patch.  The ROM's own entries are added to the symbol table then, which
+ * names the trap dispatcher, the Memory Manager and the other ROM traps.
+ */
+
+use crate::disasm;
+use crate::heap::{self, BlockKind};
+use crate::memory::{ostype, peek_u16, ROM_BASE};
+use crate::resources;
+use crate::symbols::{self, format_address};
+use crate::traps;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const TRAP_TABLE: u32 = 0x400;
+pub const TRAP_ENTRIES: u32 = 512;
+// Trap numbers below this are OS traps in the 64K ROM's single table
+const FIRST_TOOLBOX_TRAP: u32 = 0x50;
+
+lazy_static! {
+    // Original entry per trap number; empty until the table is captured
+    static ref ORIGINAL: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+}
+
+// Checked before every trap, so kept out of the lock
+static CAPTURED: AtomicBool = AtomicBool::new(false);
+
+pub struct Patch {
+    pub trap: u16,
+    pub address: u32,
+    pub original: Option<u32>,
+}
+
+/// Address a dispatch table entry refers to.
+pub fn entry_address(entry: u16) -> u32 {
+    if entry & 0x8000 != 0 {
+        (entry & 0x7FFF) as u32 * 2
+    } else {
+        ROM_BASE + entry as u32 * 2
+    }
+}
+
+/// The trap word for a dispatch table index.
+pub fn trap_word(index: u32) -> u16 {
+    if index < FIRST_TOOLBOX_TRAP { 0xA000 | index as u16 } else { 0xA800 | index as u16 }
+}
+
+/// Forgets the captured table; the next trap executed captures it again.
+pub fn reset() {
+    ORIGINAL.lock().unwrap().clear();
+    CAPTURED.store(false, Ordering::Relaxed);
+}
+
+/// Called before each A-line trap executes; the first one copies the table.
+#[inline]
+pub fn before_trap() {
+    if CAPTURED.load(Ordering::Relaxed) {
+        return;
+    }
+    CAPTURED.store(true, Ordering::Relaxed);
+    *ORIGINAL.lock().unwrap() = (0..TRAP_ENTRIES).map(|index| peek_u16(TRAP_TABLE + index * 2)).collect();
+    symbols::add_derived(&disasm::installed_entries());
+}
+
+/// Traps whose entry now points into RAM or away from the ROM's original.
+pub fn patched() -> Vec<Patch> {
+    let original = ORIGINAL.lock().unwrap();
+    (0..TRAP_ENTRIES)
+        .filter_map(|index| {
+            let entry = peek_u16(TRAP_TABLE + index * 2);
+            let first = original.get(index as usize).copied().unwrap_or(0);
+            let moved = entry & 0x8000 != 0 || (first != 0 && entry != first);
+            (entry != 0 && moved).then(|| Patch {
+                trap: trap_word(index),
+                address: entry_address(entry),
+                original: (first != 0).then(|| entry_address(first)),
+            })
+        })
+        .collect()
+}
+
+/// The heap block, and the resource if one is loaded there, containing `addr`.
+pub fn owner(addr: u32) -> String {
+    for (name, zone) in heap::zones() {
+        let zone = heap::walk(zone);
+        let block = zone.blocks.iter().find(|b| b.kind != BlockKind::Free && b.addr <= addr && addr < b.addr.wrapping_add(b.size));
+        if let Some(block) = block {
+            let resource = resources::maps().into_iter().flat_map(|m| m.resources).find(|r| r.data() == Some(block.data()));
+            return match resource {
+                Some(r) => format!("'{}' {} in {} block 0x{:06X}", ostype(r.rtype), r.id, name, block.data()),
+                None => format!("{} block 0x{:06X}", name, block.data()),
+            };
+        }
+    }
+    "not in a heap block".to_string()
+}
+
+impl Patch {
+    pub fn describe(&self) -> String {
+        let original = self.original.map(|a| format!("0x{:06X}", a)).unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "{:<28} -> {}  (ROM {})  {}",
+            traps::describe(self.trap), format_address(self.address), original, owner(self.address)
+        )
+    }
+}
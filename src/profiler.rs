@@ -0,0 +1,237 @@
+/* Execution profiler.
+ *
+ * Every executed instruction charges its cycles to its PC, and a shadow call
+ * stack is kept from JSR/BSR/TRAP/A-line calls and the returns to them.  The
+ * stack is sampled every SAMPLE_CYCLES cycles into folded-stack form, one
+ * `frame;frame;frame count` line per distinct stack, for flamegraph tools.
+ */
+
+use crate::cpu::{areg, disassemble, is_call_instruction};
+use crate::memory::peek_u16;
+use crate::{symbols, traps};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref PROFILE: Mutex<Profile> = Mutex::new(Profile::default());
+    static ref OUTPUT: Mutex<Option<String>> = Mutex::new(None);
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const SAMPLE_CYCLES: u64 = 1000;
+const MAX_DEPTH: usize = 256;
+// How far down the shadow stack to look for a matching return address
+const RETURN_SEARCH_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum FrameId {
+    Code(u32),
+    Trap(u16),
+}
+
+struct Frame {
+    id: FrameId,
+    return_pc: u32,
+    sp: u32,
+}
+
+#[derive(Default)]
+struct Profile {
+    stack: Vec<Frame>,
+    pc_cycles: HashMap<u32, u64>,
+    folded: HashMap<Vec<FrameId>, u64>,
+    total_cycles: u64,
+    until_sample: u64,
+}
+
+impl Profile {
+    fn charge(&mut self, pc: u32, cycles: u64) {
+        *self.pc_cycles.entry(pc).or_insert(0) += cycles;
+        self.total_cycles += cycles;
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.stack.len() >= MAX_DEPTH {
+            self.stack.remove(0);
+        }
+        self.stack.push(frame);
+    }
+
+    /// Pops back to the frame `new_pc` returns to, if one near the top matches.
+    fn pop_to(&mut self, new_pc: u32, sp: u32) {
+        let depth = self.stack.len();
+        let found = self.stack.iter().rev().take(RETURN_SEARCH_DEPTH)
+            .position(|f| f.return_pc == new_pc && sp > f.sp);
+        if let Some(offset) = found {
+            self.stack.truncate(depth - offset - 1);
+        }
+    }
+
+    fn sample(&mut self, cycles: u64) {
+        if self.until_sample <= cycles {
+            let weight = SAMPLE_CYCLES.max(cycles);
+            let key: Vec<FrameId> = self.stack.iter().map(|f| f.id).collect();
+            *self.folded.entry(key).or_insert(0) += weight;
+            self.until_sample = SAMPLE_CYCLES;
+        } else {
+            self.until_sample -= cycles;
+        }
+    }
+
+    fn hot(&self, limit: usize) -> Vec<(u32, u64)> {
+        let mut pcs: Vec<(u32, u64)> = self.pc_cycles.iter().map(|(&pc, &c)| (pc, c)).collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pcs.truncate(limit);
+        pcs
+    }
+}
+
+pub fn start() {
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("Profiler started");
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+    info!("Profiler stopped");
+}
+
+pub fn reset() {
+    *PROFILE.lock().unwrap() = Profile::default();
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes `<path>` (folded stacks) and `<path>.hot` (hot PCs) on shutdown.
+pub fn set_output(path: &str) {
+    *OUTPUT.lock().unwrap() = Some(path.to_string());
+}
+
+/// Accounts for one executed instruction: `pc` is where it started, `new_pc` where it left the CPU.
+pub fn record(pc: u32, new_pc: u32, cycles: i32) {
+    let cycles = cycles.max(0) as u64;
+    let sp = areg(7);
+    let mut profile = PROFILE.lock().unwrap();
+    profile.charge(pc, cycles);
+
+    let opcode = peek_u16(pc);
+    if is_call_instruction(opcode) {
+        let (_, len) = disassemble(pc);
+        let return_pc = pc.wrapping_add(len);
+        if new_pc != return_pc {
+            let id = if traps::is_trap(opcode) {
+                FrameId::Trap(traps::canonical(opcode))
+            } else {
+                FrameId::Code(new_pc)
+            };
+            profile.push(Frame { id, return_pc, sp });
+        }
+    } else {
+        profile.pop_to(new_pc, sp);
+    }
+    profile.sample(cycles);
+}
+
+fn frame_name(id: FrameId) -> String {
+    match id {
+        FrameId::Code(addr) => symbols::symbolize(addr).unwrap_or_else(|| format!("${:06X}", addr)),
+        FrameId::Trap(word) => traps::trap_name(word).map(str::to_string).unwrap_or_else(|| format!("_Trap{:04X}", word)),
+    }
+}
+
+pub fn write_folded(path: &str) -> io::Result<()> {
+    let profile = PROFILE.lock().unwrap();
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut lines: Vec<(String, u64)> = profile.folded.iter()
+        .map(|(stack, &count)| {
+            let names: Vec<String> = stack.iter().map(|&id| frame_name(id)).collect();
+            let line = if names.is_empty() { "[toplevel]".to_string() } else { names.join(";") };
+            (line, count)
+        })
+        .collect();
+    lines.sort();
+    for (line, count) in lines {
+        writeln!(out, "{} {}", line, count)?;
+    }
+    out.flush()
+}
+
+/// The `limit` PCs that used the most cycles, busiest first.
+pub fn hot_pcs(limit: usize) -> Vec<(u32, u64)> {
+    PROFILE.lock().unwrap().hot(limit)
+}
+
+pub fn write_hot_report(out: &mut dyn Write, limit: usize) -> io::Result<()> {
+    let total = PROFILE.lock().unwrap().total_cycles.max(1);
+    writeln!(out, "{:>8}  {:>12}  {:>6}  instruction", "pc", "cycles", "%")?;
+    for (pc, cycles) in hot_pcs(limit) {
+        let percent = cycles as f64 * 100.0 / total as f64;
+        let label = symbols::symbolize(pc).map(|s| format!("<{}> ", s)).unwrap_or_default();
+        writeln!(out, "{:08X}  {:>12}  {:>5.2}%  {}{}", pc, cycles, percent, label, disassemble(pc).0)?;
+    }
+    Ok(())
+}
+
+/// Writes the configured output files, if any.
+pub fn finish() {
+    let Some(path) = OUTPUT.lock().unwrap().clone() else {
+        return;
+    };
+    if let Err(e) = write_folded(&path) {
+        warn!("Failed to write folded stacks to {}: {}", path, e);
+    }
+    let hot_path = format!("{}.hot", path);
+    let result = File::create(&hot_path).and_then(|f| write_hot_report(&mut BufWriter::new(f), 200));
+    if let Err(e) = result {
+        warn!("Failed to write hot PC report to {}: {}", hot_path, e);
+    }
+    info!("Profile written to {} and {}", path, hot_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_cycles_and_ranks_hot_pcs() {
+        let mut profile = Profile::default();
+        profile.charge(0x1000, 10);
+        profile.charge(0x2000, 8);
+        profile.charge(0x1000, 4);
+        assert_eq!(profile.total_cycles, 22);
+        assert_eq!(profile.hot(1), [(0x1000, 14)]);
+        assert_eq!(profile.hot(5), [(0x1000, 14), (0x2000, 8)]);
+    }
+
+    #[test]
+    fn returns_pop_matching_frames() {
+        let mut profile = Profile::default();
+        profile.push(Frame { id: FrameId::Code(0x3000), return_pc: 0x1004, sp: 0x8000 });
+        profile.push(Frame { id: FrameId::Trap(0xA01E), return_pc: 0x3010, sp: 0x7FF0 });
+        // Same PC but a deeper stack is a recursive call, not the return
+        profile.pop_to(0x3010, 0x7FE0);
+        assert_eq!(profile.stack.len(), 2);
+        profile.pop_to(0x3010, 0x7FF6);
+        assert_eq!(profile.stack.len(), 1);
+        profile.pop_to(0x1004, 0x8004);
+        assert!(profile.stack.is_empty());
+    }
+
+    #[test]
+    fn samples_fold_the_stack() {
+        let mut profile = Profile::default();
+        profile.push(Frame { id: FrameId::Code(0x3000), return_pc: 0x1004, sp: 0x8000 });
+        profile.sample(4);
+        profile.sample(SAMPLE_CYCLES - 4);
+        profile.sample(4);
+        assert_eq!(profile.folded.get(&vec![FrameId::Code(0x3000)]), Some(&(2 * SAMPLE_CYCLES)));
+    }
+}
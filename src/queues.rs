@@ -0,0 +1,127 @@
+/* OS queues.
+ *
+ * Operating System queues share one header layout (qFlags, qHead, qTail)
+ * and link their elements through the first long.  The event queue's
+ * elements are EvQEls in SysEvtBuf; the VBL queue's are the VBLTask records
+ * installed with _VInstall.
+ */
+
+use crate::memory::{peek_u16, peek_u32};
+use crate::symbols::format_address;
+
+pub const EVENT_QUEUE: u32 = 0x14A;
+pub const VBL_QUEUE: u32 = 0x160;
+const Q_HEAD: u32 = 2;
+// Guards against a corrupt, looping queue
+const MAX_ELEMENTS: usize = 1024;
+// EvQEl offsets
+const EVT_WHAT: u32 = 6;
+const EVT_MESSAGE: u32 = 8;
+const EVT_WHEN: u32 = 12;
+const EVT_WHERE: u32 = 16;
+const EVT_MODIFIERS: u32 = 20;
+// VBLTask offsets
+const VBL_ADDR: u32 = 6;
+const VBL_COUNT: u32 = 10;
+const VBL_PHASE: u32 = 12;
+
+/// The elements of the queue whose header is at `header`, in order.
+pub fn elements(header: u32) -> Vec<u32> {
+    let mut elements = Vec::new();
+    let mut element = peek_u32(header + Q_HEAD) & 0xFFFFFF;
+    while element != 0 && elements.len() < MAX_ELEMENTS {
+        elements.push(element);
+        element = peek_u32(element) & 0xFFFFFF;
+    }
+    elements
+}
+
+fn event_name(what: u16) -> String {
+    match what {
+        0 => "nullEvent".to_string(),
+        1 => "mouseDown".to_string(),
+        2 => "mouseUp".to_string(),
+        3 => "keyDown".to_string(),
+        4 => "keyUp".to_string(),
+        5 => "autoKey".to_string(),
+        6 => "updateEvt".to_string(),
+        7 => "diskEvt".to_string(),
+        8 => "activateEvt".to_string(),
+        10 => "networkEvt".to_string(),
+        11 => "driverEvt".to_string(),
+        12..=15 => format!("app{}Evt", what - 11),
+        _ => format!("event {}", what),
+    }
+}
+
+fn modifier_names(modifiers: u16) -> String {
+    let names: Vec<&str> = [(0x0001, "active"), (0x0080, "btnState"), (0x0100, "cmd"), (0x0200, "shift"), (0x0400, "alphaLock"), (0x0800, "option")]
+        .iter()
+        .filter(|&&(bit, _)| modifiers & bit != 0)
+        .map(|&(_, name)| name)
+        .collect();
+    names.join(" ")
+}
+
+/// An event queue element: what, message, when, where and modifiers.
+pub fn describe_event(element: u32) -> String {
+    let what = peek_u16(element + EVT_WHAT);
+    let message = peek_u32(element + EVT_MESSAGE);
+    // Key events carry the character code in the low byte and the key code above it
+    let detail = match what {
+        3..=5 => {
+            let c = message as u8;
+            let shown = if (0x20..0x7F).contains(&c) { c as char } else { '.' };
+            format!(" (key ${:02X} char '{}')", (message >> 8) as u8, shown)
+        }
+        _ => String::new(),
+    };
+    let modifiers = peek_u16(element + EVT_MODIFIERS);
+    format!(
+        "0x{:06X}  {:<11} message ${:08X}{}  when {}  where ({},{})  modifiers ${:04X} {}",
+        element, event_name(what), message, detail, peek_u32(element + EVT_WHEN),
+        peek_u16(element + EVT_WHERE) as i16, peek_u16(element + EVT_WHERE + 2) as i16,
+        modifiers, modifier_names(modifiers)
+    )
+}
+
+/// A VBL task: routine, ticks until it next runs and phase.
+pub fn describe_vbl_task(element: u32) -> String {
+    format!(
+        "0x{:06X}  {}  count {}  phase {}",
+        element, format_address(peek_u32(element + VBL_ADDR) & 0xFFFFFF),
+        peek_u16(element + VBL_COUNT) as i16, peek_u16(element + VBL_PHASE) as i16
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn walk_and_decode_event_queue() {
+        put(0x35000, b"\x00\x00\x00\x03\x50\x10\x00\x03\x50\x30");
+        // keyDown 'A' with cmd and shift, linked to a null event
+        put(0x35010, b"\x00\x03\x50\x30\x00\x04\x00\x03\x00\x00\x26\x41\x00\x00\x01\x00\x00\x0A\x00\x14\x03\x00");
+        put(0x35030, b"\x00\x00\x00\x00\x00\x04\x00\x00");
+        assert_eq!(elements(0x35000), [0x35010, 0x35030]);
+        assert_eq!(
+            describe_event(0x35010),
+            "0x035010  keyDown     message $00002641 (key $26 char 'A')  when 256  where (10,20)  modifiers $0300 cmd shift"
+        );
+    }
+
+    #[test]
+    fn event_names() {
+        assert_eq!(event_name(6), "updateEvt");
+        assert_eq!(event_name(13), "app2Evt");
+        assert_eq!(event_name(99), "event 99");
+    }
+}
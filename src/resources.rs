@@ -0,0 +1,154 @@
+/* Resource Manager maps.
+ *
+ * Each open resource file has its map in memory, reached by handle from
+ * TopMapHndl and chained through the map's next-map handle.  A map starts
+ * with a copy of the file's 16-byte header, followed by the next-map handle,
+ * the file reference number, the file attributes and the offsets of the
+ * type list and name list.  Each type's reference list gives the ID, name
+ * offset, attributes and data offset of its resources, along with the
+ * handle once the resource has been loaded.
+ */
+
+use crate::heap;
+use crate::memory::{ostype, peek_pstring, peek_u16, peek_u32, peek_u8, save_memory};
+use std::fmt;
+
+const TOP_MAP_HNDL: u32 = 0xA50;
+// Resource map offsets
+const MAP_NEXT: u32 = 16;
+const MAP_REFNUM: u32 = 20;
+const MAP_ATTRS: u32 = 22;
+const MAP_TYPE_LIST: u32 = 24;
+const MAP_NAME_LIST: u32 = 26;
+const TYPE_ENTRY: u32 = 8;
+const REF_ENTRY: u32 = 12;
+// Guards against a looping chain
+const MAX_MAPS: usize = 64;
+
+pub struct Resource {
+    pub rtype: u32,
+    pub id: i16,
+    pub name: Option<String>,
+    pub attrs: u8,
+    // Handle once loaded, otherwise 0
+    pub handle: u32,
+}
+
+pub struct ResourceMap {
+    pub handle: u32,
+    pub refnum: i16,
+    pub attrs: u16,
+    pub resources: Vec<Resource>,
+}
+
+impl Resource {
+    /// Address of the resource's data, if it is loaded and not purged.
+    pub fn data(&self) -> Option<u32> {
+        if self.handle == 0 {
+            return None;
+        }
+        Some(peek_u32(self.handle) & 0xFFFFFF).filter(|&p| p != 0)
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' {:6}", ostype(self.rtype), self.id)?;
+        // System heap, purgeable, locked, protected, preload, changed
+        let flags: String = [(0x40, 'S'), (0x20, 'P'), (0x10, 'L'), (0x08, 'R'), (0x04, 'p'), (0x02, 'C')]
+            .iter()
+            .map(|&(bit, c)| if self.attrs & bit != 0 { c } else { '-' })
+            .collect();
+        write!(f, "  {}", flags)?;
+        match (self.handle, self.data()) {
+            (0, _) => write!(f, "  not loaded")?,
+            (handle, None) => write!(f, "  handle 0x{:06X} (purged)", handle)?,
+            (handle, Some(data)) => write!(f, "  handle 0x{:06X} -> 0x{:06X} ({} bytes)", handle, data, heap::block_size(data))?,
+        }
+        if let Some(name) = &self.name {
+            write!(f, "  \"{}\"", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The open resource maps, most recently opened first.
+pub fn maps() -> Vec<ResourceMap> {
+    let mut maps = Vec::new();
+    let mut handle = peek_u32(TOP_MAP_HNDL) & 0xFFFFFF;
+    while handle != 0 && maps.len() < MAX_MAPS {
+        let map = peek_u32(handle) & 0xFFFFFF;
+        if map == 0 {
+            break;
+        }
+        maps.push(ResourceMap {
+            handle,
+            refnum: peek_u16(map + MAP_REFNUM) as i16,
+            attrs: peek_u16(map + MAP_ATTRS),
+            resources: read_resources(map),
+        });
+        handle = peek_u32(map + MAP_NEXT) & 0xFFFFFF;
+    }
+    maps
+}
+
+fn read_resources(map: u32) -> Vec<Resource> {
+    let type_list = map + peek_u16(map + MAP_TYPE_LIST) as u32;
+    let name_list = map + peek_u16(map + MAP_NAME_LIST) as u32;
+    let mut resources = Vec::new();
+    // Counts are stored minus one; an empty map has $FFFF types
+    let types = peek_u16(type_list).wrapping_add(1);
+    for t in 0..types as u32 {
+        let entry = type_list + 2 + t * TYPE_ENTRY;
+        let rtype = peek_u32(entry);
+        let count = peek_u16(entry + 4) as u32 + 1;
+        let refs = type_list + peek_u16(entry + 6) as u32;
+        for r in 0..count {
+            let entry = refs + r * REF_ENTRY;
+            let name_offset = peek_u16(entry + 2);
+            resources.push(Resource {
+                rtype,
+                id: peek_u16(entry) as i16,
+                name: (name_offset != 0xFFFF).then(|| peek_pstring(name_list + name_offset as u32)),
+                attrs: peek_u8(entry + 4),
+                handle: peek_u32(entry + 8) & 0xFFFFFF,
+            });
+        }
+    }
+    resources
+}
+
+/// Finds a resource the way GetResource would, searching from the top map down.
+pub fn find(rtype: u32, id: i16) -> Option<Resource> {
+    maps().into_iter().flat_map(|m| m.resources).find(|r| r.rtype == rtype && r.id == id)
+}
+
+/// Writes a loaded resource's data to a host file, returning its size.
+pub fn dump(rtype: u32, id: i16, path: &str) -> Result<u32, String> {
+    let resource = find(rtype, id).ok_or_else(|| format!("No resource '{}' {} in the open maps", ostype(rtype), id))?;
+    let data = resource.data().ok_or_else(|| format!("Resource '{}' {} is not loaded", ostype(rtype), id))?;
+    let len = heap::block_size(data);
+    save_memory(path, data, len).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(len)
+}
+
+/// Packs a four-character type given on the command line.
+pub fn parse_type(s: &str) -> Option<u32> {
+    let bytes = s.as_bytes();
+    (bytes.len() <= 4 && !bytes.is_empty())
+        .then(|| (0..4).fold(0, |acc, i| (acc << 8) | *bytes.get(i).unwrap_or(&b' ') as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_type_pads_with_spaces() {
+        assert_eq!(parse_type("CODE"), Some(0x434F4445));
+        assert_eq!(parse_type("STR"), Some(0x53545220));
+        assert_eq!(parse_type("snd "), Some(0x736E6420));
+        assert_eq!(parse_type(""), None);
+        assert_eq!(parse_type("TOOLONG"), None);
+    }
+}
@@ -0,0 +1,177 @@
+/* Segment Loader tracking.
+ *
+ * _LoadSeg takes a segment number on the stack and loads that CODE
+ * resource.  The load is complete once the calling jump table entry has
+ * been rewritten to a JMP, or execution is back at the caller (the entry,
+ * or the instruction after a direct _LoadSeg); the segment's code range is
+ * then recorded, so PCs inside it read as `CODE n + $off`
+ * and symbols given for segment `CODEn` resolve.  Offsets count from the
+ * first instruction, past the 4-byte segment header.  _UnloadSeg takes an
+ * address inside the segment and forgets it.
+ *
+ * The A5 world is the application globals below A5, the application
+ * parameters at A5 and the jump table at A5+CurJTOffset, whose 8-byte
+ * entries are either unloaded (offset, MOVE.W #seg,-(SP), _LoadSeg) or
+ * loaded (segment, JMP addr).
+ */
+
+use crate::cpu::areg;
+use crate::heap;
+use crate::memory::{peek_u16, peek_u32};
+use crate::resources;
+use crate::symbols::{self, format_address};
+use lazy_static::lazy_static;
+use log::info;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+static LOGGING: AtomicBool = AtomicBool::new(false);
+// Set while a _LoadSeg is running, so other instructions skip the lock
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+const LOAD_SEG: u16 = 0xA9F0;
+const UNLOAD_SEG: u16 = 0xA9F1;
+const CURRENT_A5: u32 = 0x904;
+const CUR_JT_OFFSET: u32 = 0x934;
+const CODE: u32 = 0x434F4445;
+const SEGMENT_HEADER: u32 = 4;
+const JT_ENTRY: u32 = 8;
+// Opcodes that identify unloaded and loaded jump table entries
+const MOVE_W_IMM_PREDEC: u16 = 0x3F3C;
+const JMP_ABS_L: u16 = 0x4EF9;
+// CODE 0 header: sizes above and below A5, jump table size and offset
+const CODE0_BELOW_A5: u32 = 4;
+const CODE0_JT_SIZE: u32 = 8;
+const MAX_JT_ENTRIES: u32 = 4096;
+
+struct PendingLoad {
+    segment: i16,
+    // Jump table entry that called _LoadSeg, if it looks like it came from one
+    entry: Option<u32>,
+    // The instruction after the _LoadSeg, where a direct call returns
+    next: u32,
+}
+
+#[derive(Default)]
+struct State {
+    pending: Option<PendingLoad>,
+    // (segment, first instruction, end) of loaded segments
+    loaded: Vec<(i16, u32, u32)>,
+}
+
+pub fn set_logging(enabled: bool) {
+    LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+pub fn logging() -> bool {
+    LOGGING.load(Ordering::Relaxed)
+}
+
+/// Called with each instruction before it executes.
+pub fn before_instruction(pc: u32, opcode: u16) {
+    match opcode {
+        LOAD_SEG => {
+            let segment = peek_u16(areg(7)) as i16;
+            if logging() {
+                info!("_LoadSeg CODE {} from {}", segment, format_address(pc));
+            }
+            // An unloaded entry is offset, MOVE.W #seg,-(SP), _LoadSeg
+            let entry = (peek_u16(pc.wrapping_sub(4)) == MOVE_W_IMM_PREDEC).then(|| pc.wrapping_sub(6));
+            STATE.lock().unwrap().pending = Some(PendingLoad { segment, entry, next: pc.wrapping_add(2) });
+            PENDING.store(true, Ordering::Relaxed);
+        }
+        UNLOAD_SEG => {
+            let routine = peek_u32(areg(7)) & 0xFFFFFF;
+            let segment = locate(routine).map(|(segment, _)| segment);
+            if logging() {
+                match segment {
+                    Some(segment) => info!("_UnloadSeg CODE {} from {}", segment, format_address(pc)),
+                    None => info!("_UnloadSeg 0x{:06X} (no loaded segment) from {}", routine, format_address(pc)),
+                }
+            }
+            if let Some(segment) = segment {
+                STATE.lock().unwrap().loaded.retain(|&(s, _, _)| s != segment);
+            }
+        }
+        _ if PENDING.load(Ordering::Relaxed) => {
+            let segment = {
+                let mut state = STATE.lock().unwrap();
+                let Some(load) = &state.pending else {
+                    return;
+                };
+                // The loader rewrites the entry to segment, JMP addr and then jumps to it
+                let done = pc == load.next
+                    || load.entry.map(|entry| entry.wrapping_add(2)).is_some_and(|jmp| pc == jmp || peek_u16(jmp) == JMP_ABS_L);
+                if !done {
+                    return;
+                }
+                let segment = load.segment;
+                state.pending = None;
+                segment
+            };
+            PENDING.store(false, Ordering::Relaxed);
+            loaded(segment);
+        }
+        _ => {}
+    }
+}
+
+fn loaded(segment: i16) {
+    let Some(data) = resources::find(CODE, segment).and_then(|r| r.data()) else {
+        info!("CODE {} not found after _LoadSeg", segment);
+        return;
+    };
+    let start = data + SEGMENT_HEADER;
+    let end = data + heap::block_size(data);
+    {
+        let mut state = STATE.lock().unwrap();
+        state.loaded.retain(|&(s, _, _)| s != segment);
+        state.loaded.push((segment, start, end));
+    }
+    symbols::set_segment_base(&format!("CODE{}", segment), start);
+    if logging() {
+        info!("CODE {} loaded at 0x{:06X}..0x{:06X}", segment, start, end);
+    }
+}
+
+/// The loaded segment containing `addr`, with the offset into it.
+pub fn locate(addr: u32) -> Option<(i16, u32)> {
+    let state = STATE.lock().unwrap();
+    state.loaded.iter().find(|&&(_, start, end)| start <= addr && addr < end).map(|&(segment, start, _)| (segment, addr - start))
+}
+
+/// A5, the globals and jump table bounds, and every jump table entry.
+pub fn a5_world() -> String {
+    let a5 = peek_u32(CURRENT_A5) & 0xFFFFFF;
+    let jump_table = a5 + peek_u16(CUR_JT_OFFSET) as u32;
+    let code0 = resources::find(CODE, 0).and_then(|r| r.data());
+    let mut out = format!("CurrentA5 0x{:06X}  (A5 register 0x{:06X})\n", a5, areg(5));
+    if let Some(code0) = code0 {
+        let below = peek_u32(code0 + CODE0_BELOW_A5);
+        out += &format!("  application globals 0x{:06X}..0x{:06X} ({} bytes)\n", a5.wrapping_sub(below), a5, below);
+    }
+    out += &format!("  QuickDraw globals pointer 0x{:06X}\n", peek_u32(a5) & 0xFFFFFF);
+    // Without CODE 0 in memory, stop at the first entry that is neither form
+    let count = code0.map(|c| peek_u32(c + CODE0_JT_SIZE) / JT_ENTRY).unwrap_or(MAX_JT_ENTRIES).min(MAX_JT_ENTRIES);
+    out += &format!("  jump table at 0x{:06X} (A5+${:X})\n", jump_table, jump_table - a5);
+    for i in 0..count {
+        let entry = jump_table + i * JT_ENTRY;
+        let line = match (peek_u16(entry + 2), peek_u16(entry + 6)) {
+            (MOVE_W_IMM_PREDEC, LOAD_SEG) => {
+                format!("CODE {} + ${:X}  unloaded", peek_u16(entry + 4) as i16, peek_u16(entry))
+            }
+            (JMP_ABS_L, _) => {
+                let target = peek_u32(entry + 4) & 0xFFFFFF;
+                format!("CODE {}  loaded -> {}", peek_u16(entry) as i16, format_address(target))
+            }
+            _ if code0.is_none() => break,
+            _ => "?".to_string(),
+        };
+        out += &format!("    #{:<4} A5+${:04X}  {}\n", i, entry - a5, line);
+    }
+    out
+}
@@ -0,0 +1,128 @@
+/* Uninitialized read detection.
+ *
+ * A shadow bitmap records which RAM bytes the guest has written.  Started
+ * at power-on all of RAM begins unwritten; started later, RAM as it stands
+ * counts as initialized.  Blocks returned by _NewPtr and _NewHandle (without
+ * the clear bit) are marked unwritten again.  A read that touches an
+ * unwritten byte is logged the first time each PC does it and counted
+ * afterwards.  Instruction fetches come through Musashi's separate
+ * immediate-read hooks and never get here.  Writes from the host side
+ * (loading files, debugger pokes and edits, freezes) count as written too.
+ */
+
+use crate::cpu::{areg, dreg};
+use crate::memory::{peek_u32, rom_offset, RAM_SIZE};
+use crate::symbols::format_address;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const NEW_PTR: u16 = 0xA11E;
+const NEW_HANDLE: u16 = 0xA122;
+// Trap word bits for the system heap and clearing the block
+const TRAP_FLAGS: u16 = 0x0600;
+const CLEAR_FLAG: u16 = 0x0200;
+
+#[derive(Default)]
+struct State {
+    written: Vec<u64>,
+    // (opcode, return PC, size) of an allocation in progress
+    pending: Option<(u16, u32, u32)>,
+    // PC -> (first address read, number of reads)
+    reads: BTreeMap<u32, (u32, u64)>,
+}
+
+impl State {
+    fn set(&mut self, start: u32, len: u32, written: bool) {
+        for addr in start..start.saturating_add(len).min(RAM_SIZE as u32) {
+            let (word, bit) = ((addr / 64) as usize, addr % 64);
+            if written {
+                self.written[word] |= 1 << bit;
+            } else {
+                self.written[word] &= !(1 << bit);
+            }
+        }
+    }
+
+    fn is_written(&self, addr: u32) -> bool {
+        self.written[(addr / 64) as usize] & (1 << (addr % 64)) != 0
+    }
+}
+
+/// Starts tracking; with `from_power_on` all of RAM starts out unwritten.
+pub fn start(from_power_on: bool) {
+    let mut state = STATE.lock().unwrap();
+    let fill = if from_power_on { 0 } else { !0 };
+    state.written = vec![fill; RAM_SIZE / 64];
+    state.pending = None;
+    state.reads.clear();
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("Uninitialized read detection on");
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn on_write(addr: u32, len: u32) {
+    if addr < RAM_SIZE as u32 {
+        STATE.lock().unwrap().set(addr, len, true);
+    }
+}
+
+pub fn check_read(addr: u32, len: u32, pc: u32) {
+    // ROM overlaying RAM at 0 during reset reads as ROM
+    if addr >= RAM_SIZE as u32 || rom_offset(addr).is_some() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    if (0..len).map(|i| addr.wrapping_add(i)).all(|a| a >= RAM_SIZE as u32 || state.is_written(a)) {
+        return;
+    }
+    let entry = state.reads.entry(pc).or_insert((addr, 0));
+    entry.1 += 1;
+    if entry.1 == 1 {
+        drop(state);
+        warn!("Uninitialized read of 0x{:06X} ({} bytes) by {}", addr, len, format_address(pc));
+    }
+}
+
+/// Called with each instruction before it executes, to follow allocations.
+pub fn before_instruction(pc: u32, opcode: u16) {
+    let mut state = STATE.lock().unwrap();
+    if let Some((trap, return_pc, size)) = state.pending {
+        if pc == return_pc {
+            state.pending = None;
+            let data = if trap & !TRAP_FLAGS == NEW_HANDLE {
+                let handle = areg(0) & 0xFFFFFF;
+                if handle == 0 { 0 } else { peek_u32(handle) & 0xFFFFFF }
+            } else {
+                areg(0) & 0xFFFFFF
+            };
+            if data != 0 && trap & CLEAR_FLAG == 0 {
+                state.set(data, size, false);
+            }
+        }
+    }
+    let trap = opcode & !TRAP_FLAGS;
+    if trap == NEW_PTR || trap == NEW_HANDLE {
+        state.pending = Some((opcode, pc.wrapping_add(2), dreg(0)));
+    }
+}
+
+/// Each PC that read unwritten memory, with the first address and the read count.
+pub fn reads() -> Vec<(u32, u32, u64)> {
+    STATE.lock().unwrap().reads.iter().map(|(&pc, &(addr, count))| (pc, addr, count)).collect()
+}
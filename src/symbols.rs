@@ -0,0 +1,357 @@
+/* Symbol tables.
+ *
+ * Symbol files are plain text, one `ADDRESS NAME` pair per line (hex
+ * address, `#` or `;` starts a comment).  Two directives group lines:
+ *
+ *   rom 4D1F8172     following symbols only apply to the ROM with this checksum
+ *   rom *            following symbols apply to any ROM
+ *   segment CODE3    following addresses are offsets into segment CODE3
+ *   segment -        back to absolute addresses
+ *
+ * Segment-relative symbols resolve once the segment's base address is known,
+ * either from the debugger (`sym base`) or when the segment is loaded.
+ *
+ * Labels for known ROMs live in symbols/<checksum>.sym and are compiled in;
+ * a file of the same name in the working directory is loaded after them.
+ * Routine entry points are also derived from the ROM itself: the reset
+ * vector when the ROM is loaded, and the exception vectors and trap dispatch
+ * table (the trap dispatcher, Memory Manager and every other ROM trap) once
+ * the ROM has installed them.  Derived names never replace a loaded one.
+ */
+
+use crate::disasm;
+use crate::lowmem;
+use crate::segments;
+use crate::memory::{parse_address, peek_u32, ROM_BASE};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref SYMBOLS: Mutex<SymbolTable> = Mutex::new(SymbolTable::default());
+}
+
+// Directory searched for `<checksum>.sym` ROM label files
+const ROM_SYMBOL_DIR: &str = "symbols";
+// Labels built in for known ROMs, by checksum
+const BUILTIN_ROM_LABELS: &[(u32, &str)] = &[
+    (0x28BA61CE, include_str!("../symbols/28BA61CE.sym")),
+    (0x28BA4E50, include_str!("../symbols/28BA4E50.sym")),
+];
+// Addresses further than this past the nearest symbol are shown bare
+const MAX_OFFSET: u32 = 0x10000;
+
+#[derive(Default)]
+struct SymbolTable {
+    by_addr: BTreeMap<u32, String>,
+    by_name: HashMap<String, u32>,
+    // Segment name -> (offset, symbol name)
+    relative: HashMap<String, Vec<(u32, String)>>,
+    bases: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    fn insert(&mut self, addr: u32, name: &str) {
+        if let Some(old) = self.by_addr.insert(addr, name.to_string()) {
+            self.by_name.remove(&old);
+        }
+        self.by_name.insert(name.to_string(), addr);
+    }
+
+    fn insert_relative(&mut self, segment: &str, offset: u32, name: &str) {
+        if let Some(&base) = self.bases.get(segment) {
+            self.insert(base.wrapping_add(offset), name);
+        }
+        self.relative.entry(segment.to_string()).or_default().push((offset, name.to_string()));
+    }
+
+    fn set_base(&mut self, segment: &str, base: u32) {
+        let symbols = self.relative.get(segment).cloned().unwrap_or_default();
+        if let Some(old) = self.bases.insert(segment.to_string(), base) {
+            for (offset, name) in &symbols {
+                let addr = old.wrapping_add(*offset);
+                if self.by_addr.get(&addr) == Some(name) {
+                    self.by_addr.remove(&addr);
+                    self.by_name.remove(name);
+                }
+            }
+        }
+        for (offset, name) in &symbols {
+            self.insert(base.wrapping_add(*offset), name);
+        }
+    }
+}
+
+/// The checksum stored in the first long of the ROM image.
+pub fn rom_checksum() -> u32 {
+    peek_u32(ROM_BASE)
+}
+
+// A parsed symbol: segment (None for absolute), address or offset, name
+type Entry = (Option<String>, u32, String);
+
+/// Loads a symbol file, returning how many symbols applied to this machine.
+pub fn load(path: &str) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    load_text(path, &text)
+}
+
+fn load_text(source: &str, text: &str) -> Result<usize, String> {
+    let entries = parse(source, text, rom_checksum())?;
+    let mut table = SYMBOLS.lock().unwrap();
+    for (segment, addr, name) in &entries {
+        match segment {
+            Some(seg) => table.insert_relative(seg, *addr, name),
+            None => table.insert(*addr, name),
+        }
+    }
+    info!("Loaded {} symbols from {}", entries.len(), source);
+    Ok(entries.len())
+}
+
+/// Parses symbol file text, keeping the symbols that apply to the ROM with `checksum`.
+fn parse(source: &str, text: &str, checksum: u32) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut applies = true;
+    let mut segment: Option<String> = None;
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.split(['#', ';']).next().unwrap_or("").trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => {}
+            ["rom", "*"] => applies = true,
+            ["rom", sum] => match u32::from_str_radix(sum.trim_start_matches("0x").trim_start_matches('$'), 16) {
+                Ok(sum) => applies = sum == checksum,
+                Err(_) => return Err(format!("{}:{}: bad ROM checksum '{}'", source, line_no + 1, sum)),
+            },
+            ["segment", "-"] => segment = None,
+            ["segment", name] => segment = Some(name.to_string()),
+            [addr, name] => {
+                let Some(addr) = parse_hex(addr) else {
+                    return Err(format!("{}:{}: bad address '{}'", source, line_no + 1, addr));
+                };
+                if applies {
+                    entries.push((segment.clone(), addr, name.to_string()));
+                }
+            }
+            _ => return Err(format!("{}:{}: expected 'ADDRESS NAME'", source, line_no + 1)),
+        }
+    }
+    Ok(entries)
+}
+
+// Symbol files use bare hex; prefixed forms are accepted too
+fn parse_hex(s: &str) -> Option<u32> {
+    if s.starts_with("0x") || s.starts_with('$') {
+        parse_address(s)
+    } else {
+        u32::from_str_radix(s, 16).ok()
+    }
+}
+
+/// Loads the built-in labels for the current ROM, then `symbols/<checksum>.sym` if there is one
+/// and it isn't the built-in file itself, then labels the reset entry.
+pub fn load_rom_labels() {
+    let checksum = rom_checksum();
+    let builtin = BUILTIN_ROM_LABELS.iter().find(|&&(sum, _)| sum == checksum).map(|&(_, text)| text);
+    if let Some(text) = builtin {
+        if let Err(e) = load_text(&format!("built-in {:08X}.sym", checksum), text) {
+            warn!("{}", e);
+        }
+    }
+    let path = Path::new(ROM_SYMBOL_DIR).join(format!("{:08X}.sym", checksum));
+    match fs::read_to_string(&path) {
+        Ok(text) if Some(text.as_str()) == builtin => {}
+        Ok(text) => {
+            if let Err(e) = load_text(&path.to_string_lossy(), &text) {
+                warn!("{}", e);
+            }
+        }
+        Err(_) if !path.exists() => {}
+        Err(e) => warn!("Failed to read {}: {}", path.display(), e),
+    }
+    add_derived(&disasm::reset_entry());
+}
+
+/// Adds labels found in the ROM image or its installed tables, skipping any address or name already defined.
+pub fn add_derived(entries: &[(u32, String)]) {
+    let mut table = SYMBOLS.lock().unwrap();
+    for (addr, name) in entries {
+        if !table.by_addr.contains_key(addr) && !table.by_name.contains_key(name) {
+            table.insert(*addr, name);
+        }
+    }
+}
+
+pub fn add(addr: u32, name: &str) {
+    SYMBOLS.lock().unwrap().insert(addr, name);
+}
+
+/// Sets where a segment is loaded, (re)placing its relative symbols.
+pub fn set_segment_base(segment: &str, base: u32) {
+    SYMBOLS.lock().unwrap().set_base(segment, base);
+}
+
+/// Nearest symbol at or below `addr`, with the offset from it.
+pub fn lookup(addr: u32) -> Option<(String, u32)> {
+    let table = SYMBOLS.lock().unwrap();
+    let (&start, name) = table.by_addr.range(..=addr).next_back()?;
+    (addr - start < MAX_OFFSET).then(|| (name.clone(), addr - start))
+}
+
+/// The symbol defined exactly at `addr`.
+pub fn exact(addr: u32) -> Option<String> {
+    SYMBOLS.lock().unwrap().by_addr.get(&addr).cloned()
+}
+
+/// `name` or `name+$offset` for `addr`, if it is near a symbol, or
+/// `CODE n + $offset` inside a loaded segment with no closer symbol.
+pub fn symbolize(addr: u32) -> Option<String> {
+    let segment = segments::locate(addr);
+    match (lookup(addr), segment) {
+        (Some((name, offset)), segment) if segment.is_none_or(|(_, seg_offset)| offset <= seg_offset) => {
+            if offset == 0 {
+                Some(name)
+            } else {
+                Some(format!("{}+${:X}", name, offset))
+            }
+        }
+        (_, Some((segment, offset))) => Some(format!("CODE {} + ${:X}", segment, offset)),
+        _ => None,
+    }
+}
+
+/// `0x00ABCD <name+$12>`, or just the address when no symbol is near.
+pub fn format_address(addr: u32) -> String {
+    match symbolize(addr) {
+        Some(sym) => format!("0x{:06X} <{}>", addr, sym),
+        None => format!("0x{:06X}", addr),
+    }
+}
+
+/// Resolves `name` or `name+OFFSET` to an address.
+pub fn resolve(s: &str) -> Option<u32> {
+    let (name, offset) = match s.split_once('+') {
+        Some((name, offset)) => (name, parse_address(offset)?),
+        None => (s, 0),
+    };
+    let addr = *SYMBOLS.lock().unwrap().by_name.get(name)?;
+    let resolved = addr.checked_add(offset);
+    if resolved.is_none() {
+        warn!("{}: address past the end of memory", s);
+    }
+    resolved
+}
+
+/// Parses a number like `memory::parse_address`, falling back to a symbol or low-memory global name.
+pub fn parse_symbolic(s: &str) -> Option<u32> {
+    parse_address(s).or_else(|| resolve(s)).or_else(|| lowmem::by_name(s).map(|g| g.addr))
+}
+
+/// Symbols whose name contains `filter`, in address order.
+pub fn list(filter: &str) -> Vec<(u32, String)> {
+    SYMBOLS.lock().unwrap().by_addr.iter()
+        .filter(|(_, name)| name.contains(filter))
+        .map(|(&addr, name)| (addr, name.clone()))
+        .collect()
+}
+
+/// Appends `; name` to disassembly whose absolute (`$16a.w`, `$401234.l`) or
+/// PC-relative (branch targets, `; ($401234)`) operands have a symbol or, for
+/// absolute operands, a low-memory global.  Immediates and displacements
+/// (`#$1234`, `($12,A5)`) are left alone.
+pub fn annotate(text: &str) -> String {
+    let mut names = Vec::new();
+    let mut start = 0;
+    while let Some(pos) = text[start..].find('$').map(|pos| start + pos) {
+        let hex: String = text[pos + 1..].chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+        start = pos + 1 + hex.len();
+        let Ok(addr) = u32::from_str_radix(&hex, 16) else {
+            continue;
+        };
+        let (before, after) = (&text[..pos], &text[start..]);
+        let absolute = after.starts_with(".w") || after.starts_with(".l");
+        // Musashi prints branch targets bare and PC-relative operands' targets as `; ($addr)`
+        let branch = before.ends_with(' ') && (after.is_empty() || after.starts_with(' '));
+        let pc_relative = branch || before.ends_with("; (");
+        if !absolute && !pc_relative {
+            continue;
+        }
+        let global = absolute.then(|| lowmem::describe(addr)).flatten();
+        if let Some(name) = global.or_else(|| symbolize(addr & 0xFFFFFF)) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        text.to_string()
+    } else {
+        format!("{:<32} ; {}", text, names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_absolute_and_segment_symbols() {
+        let text = "# comment\n400010 Start ; trailing\nsegment CODE1\n0x1C Main\nsegment -\n$1000 Buffer\n";
+        let entries = parse("test", text, 0).unwrap();
+        assert_eq!(entries, vec![
+            (None, 0x400010, "Start".to_string()),
+            (Some("CODE1".to_string()), 0x1C, "Main".to_string()),
+            (None, 0x1000, "Buffer".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_rom_directive_filters_by_checksum() {
+        let text = "rom 28BA61CE\n400100 Only64K\nrom 4D1F8172\n400200 OnlyPlus\nrom *\n400300 Any\n";
+        let names = |checksum| parse("test", text, checksum).unwrap().into_iter().map(|(_, _, name)| name).collect::<Vec<_>>();
+        assert_eq!(names(0x28BA61CE), ["Only64K", "Any"]);
+        assert_eq!(names(0x4D1F8172), ["OnlyPlus", "Any"]);
+    }
+
+    #[test]
+    fn parse_reports_bad_lines() {
+        assert_eq!(parse("f.sym", "zz Name\n", 0), Err("f.sym:1: bad address 'zz'".to_string()));
+        assert_eq!(parse("f.sym", "\nrom xyz\n", 0), Err("f.sym:2: bad ROM checksum 'xyz'".to_string()));
+        assert_eq!(parse("f.sym", "1 2 3\n", 0), Err("f.sym:1: expected 'ADDRESS NAME'".to_string()));
+    }
+
+    #[test]
+    fn annotate_only_names_addresses() {
+        add(0x412340, "AnnotateTarget");
+        assert!(annotate("jsr     $412340.l").ends_with("; AnnotateTarget"));
+        assert!(annotate("bsr     $412340").ends_with("; AnnotateTarget"));
+        assert!(annotate("lea     ($10,PC), A0; ($412340)").ends_with("; AnnotateTarget"));
+        assert_eq!(annotate("move.l  #$412340, D0"), "move.l  #$412340, D0");
+        assert_eq!(annotate("move.w  ($4123,A5), D0"), "move.w  ($4123,A5), D0");
+    }
+
+    #[test]
+    fn resolve_rejects_overflow() {
+        add(0xFFFFFFF0, "ResolveNearTop");
+        assert_eq!(resolve("ResolveNearTop+4"), Some(0xFFFFFFF4));
+        assert_eq!(resolve("ResolveNearTop+0x20"), None);
+    }
+
+    #[test]
+    fn derived_labels_keep_loaded_names() {
+        add(0x412400, "DerivedLoaded");
+        add_derived(&[(0x412400, "_Derived1".to_string()), (0x412410, "DerivedLoaded".to_string()), (0x412420, "_Derived2".to_string())]);
+        assert_eq!(exact(0x412400).as_deref(), Some("DerivedLoaded"));
+        assert_eq!(exact(0x412410), None);
+        assert_eq!(resolve("_Derived2"), Some(0x412420));
+    }
+
+    #[test]
+    fn builtin_labels_parse() {
+        for &(checksum, text) in BUILTIN_ROM_LABELS {
+            assert!(!parse("builtin", text, checksum).unwrap().is_empty());
+        }
+    }
+}
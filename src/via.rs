@@ -203,6 +203,16 @@ impl Via {
         val
     }
 
+    pub fn dump(&self) -> String {
+        format!(
+            "VIA RA: {:02X} (DDRA {:02X})  RB: {:02X} (DDRB {:02X})  SR: {:02X}  ACR: {:02X}\n\
+             VIA IFR: {:02X}  IER: {:02X}  IRQ: {}  SR tx pending: {:?}",
+            self.regs[VIA_RA], self.regs[VIA_DDRA], self.regs[VIA_RB], self.regs[VIA_DDRB],
+            self.regs[VIA_SR], self.regs[VIA_ACR], self.read_ifr(), 0x80 | self.irq_enable,
+            self.irq_status, self.sr_tx_pending
+        )
+    }
+
     pub fn tick(&mut self, _time_us: u64) {
         // FIXME: timer support
     }
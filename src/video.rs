@@ -1,10 +1,11 @@
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
+use crate::debugger;
 use crate::memory::{RAM, VIDEO_BASE};
 
 const WIDTH: u32 = 512;
@@ -61,17 +62,22 @@ impl MacVideo {
             match event {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => {
-                        if let Some(VirtualKeyCode::Escape) = input.virtual_keycode {
-                            *control_flow = ControlFlow::Exit;
+                    WindowEvent::KeyboardInput { input, .. } => match input.virtual_keycode {
+                        Some(VirtualKeyCode::Escape) => *control_flow = ControlFlow::Exit,
+                        Some(VirtualKeyCode::F12) if input.state == ElementState::Pressed => {
+                            if !debugger::is_paused() {
+                                debugger::request_break("Break key pressed");
+                            }
                         }
-                    }
+                        _ => {}
+                    },
                     _ => {}
                 },
                 Event::RedrawRequested(_) => {
                     emulation_step();
                     self.update();
                 }
+                Event::LoopDestroyed => crate::shutdown(),
                 Event::MainEventsCleared => {
                     self.window.request_redraw();
                 }
@@ -0,0 +1,143 @@
+/* Hang watchdog.
+ *
+ * Tracks the range of PCs executed over a window of emulated time.  If the
+ * PC stayed within WINDOW_BYTES for the whole period, interrupts were masked
+ * throughout and the Ticks global never moved, the guest is
+ * considered hung: the loop is reported with its disassembly, the machine
+ * stops and, if configured, a RAM snapshot is written.
+ */
+
+use crate::cpu::{disassemble, get_sr, register_dump, CLOCK_HZ};
+use crate::debugger;
+use crate::events::{self, MachineEvent};
+use crate::memory::{peek_u32, save_ram};
+use crate::symbols::{self, format_address};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// PC range a loop has to stay within to count as no progress
+const WINDOW_BYTES: u32 = 256;
+// Longest loop listing in the report
+const MAX_LISTING: u32 = 64;
+const TICKS: u32 = 0x16A;
+// Any nonzero mask blocks the VIA, which interrupts at level 1
+const SR_IPL_MASK: u32 = 0x0700;
+
+#[derive(Default)]
+struct State {
+    period_cycles: u64,
+    snapshot: Option<String>,
+    elapsed: u64,
+    min_pc: u32,
+    max_pc: u32,
+    ticks: u32,
+    masked: bool,
+    fresh: bool,
+}
+
+impl State {
+    fn restart(&mut self, pc: u32) {
+        self.elapsed = 0;
+        self.min_pc = pc;
+        self.max_pc = pc;
+        self.ticks = peek_u32(TICKS);
+        self.masked = true;
+        self.fresh = false;
+    }
+}
+
+/// Arms the watchdog with a period of `seconds` of emulated time.
+pub fn start(seconds: f64) {
+    let mut state = STATE.lock().unwrap();
+    state.period_cycles = (seconds * CLOCK_HZ as f64) as u64;
+    state.fresh = true;
+    ENABLED.store(true, Ordering::Relaxed);
+    info!("Hang watchdog armed: {} emulated seconds", seconds);
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Period in emulated seconds, if armed.
+pub fn period() -> Option<f64> {
+    enabled().then(|| STATE.lock().unwrap().period_cycles as f64 / CLOCK_HZ as f64)
+}
+
+/// Also write a RAM snapshot to `path` (and a report to `path.txt`) when a hang is detected.
+pub fn set_snapshot(path: &str) {
+    STATE.lock().unwrap().snapshot = Some(path.to_string());
+}
+
+/// Accounts for one executed instruction.
+pub fn record(pc: u32, cycles: i32) {
+    let mut state = STATE.lock().unwrap();
+    if state.fresh {
+        state.restart(pc);
+    }
+    state.min_pc = state.min_pc.min(pc);
+    state.max_pc = state.max_pc.max(pc);
+    if state.max_pc - state.min_pc > WINDOW_BYTES {
+        state.restart(pc);
+        return;
+    }
+    if get_sr() & SR_IPL_MASK == 0 {
+        state.masked = false;
+    }
+    state.elapsed += cycles.max(0) as u64;
+    if state.elapsed < state.period_cycles {
+        return;
+    }
+    let ticks_stalled = peek_u32(TICKS) == state.ticks;
+    let (min_pc, max_pc, masked) = (state.min_pc, state.max_pc, state.masked);
+    let snapshot = state.snapshot.clone();
+    let seconds = state.period_cycles as f64 / CLOCK_HZ as f64;
+    state.restart(pc);
+    drop(state);
+    if masked && ticks_stalled {
+        hang(min_pc, max_pc, seconds, snapshot);
+    }
+}
+
+fn hang(min_pc: u32, max_pc: u32, seconds: f64, snapshot: Option<String>) {
+    let reason = format!(
+        "Hang: PC stayed within {}..0x{:06X} for {} emulated seconds with interrupts masked and Ticks stalled",
+        format_address(min_pc), max_pc, seconds
+    );
+    let mut report = format!("{}\n", reason);
+    let mut pc = min_pc;
+    while pc <= max_pc && pc < min_pc + MAX_LISTING * 2 {
+        if let Some(name) = symbols::exact(pc) {
+            report += &format!("{}:\n", name);
+        }
+        let (text, len) = disassemble(pc);
+        report += &format!("  0x{:06X}  {}\n", pc, text);
+        pc += len.max(2);
+    }
+    warn!("{}", reason);
+    println!("{}", report);
+
+    if let Some(path) = snapshot {
+        let text = format!("{}\nRegisters:\n{}", report, register_dump());
+        let result = save_ram(&path).and_then(|_| fs::write(format!("{}.txt", path), text));
+        match result {
+            Ok(()) => info!("Hang snapshot written to {} and {}.txt", path, path),
+            Err(e) => warn!("Failed to write hang snapshot to {}: {}", path, e),
+        }
+    }
+    events::emit(MachineEvent::Hang { min_pc, max_pc });
+    debugger::request_break(&reason);
+}
@@ -0,0 +1,226 @@
+use lazy_static::lazy_static;
+use log::warn;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::lowmem;
+use crate::memory::parse_address;
+use crate::symbols::parse_symbolic;
+
+lazy_static! {
+    static ref WATCHPOINTS: Mutex<Vec<Watchpoint>> = Mutex::new(Vec::new());
+    static ref PENDING_HIT: Mutex<Option<WatchHit>> = Mutex::new(None);
+    static ref LAST_HIT: Mutex<Option<WatchHit>> = Mutex::new(None);
+}
+
+// Checked on every CPU memory access, so keep it a single relaxed load
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// Checked after every instruction; set while PENDING_HIT holds a hit
+static HIT_PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Change,
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchKind::Read => write!(f, "read"),
+            WatchKind::Write => write!(f, "write"),
+            WatchKind::Change => write!(f, "change"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, addr: u32, size: u32) -> bool {
+        addr < self.start.wrapping_add(self.len) && self.start < addr.wrapping_add(size)
+    }
+
+    /// Whether a big-endian access of `size` bytes changes any byte inside the range.
+    fn changes(&self, addr: u32, size: u32, old: u32, new: u32) -> bool {
+        (0..size).any(|i| {
+            let shift = 8 * (size - 1 - i);
+            self.overlaps(addr.wrapping_add(i), 1) && (old >> shift) as u8 != (new >> shift) as u8
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchHit {
+    pub kind: WatchKind,
+    pub addr: u32,
+    pub size: u32,
+    pub pc: u32,
+    pub old: u32,
+    pub new: u32,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = (self.size * 2) as usize;
+        let name = lowmem::describe(self.addr).map(|n| format!(" ({})", n)).unwrap_or_default();
+        match self.kind {
+            WatchKind::Read => write!(
+                f, "Watchpoint ({}) at 0x{:06X}{} size {}: value 0x{:0w$X} by PC 0x{:06X}",
+                self.kind, self.addr, name, self.size, self.new, self.pc, w = digits
+            ),
+            _ => write!(
+                f, "Watchpoint ({}) at 0x{:06X}{} size {}: 0x{:0w$X} -> 0x{:0w$X} by PC 0x{:06X}",
+                self.kind, self.addr, name, self.size, self.old, self.new, self.pc, w = digits
+            ),
+        }
+    }
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn add(start: u32, len: u32, kind: WatchKind) -> usize {
+    let mut list = WATCHPOINTS.lock().unwrap();
+    list.push(Watchpoint { start, len: len.max(1), kind });
+    ACTIVE.store(true, Ordering::Relaxed);
+    list.len() - 1
+}
+
+pub fn remove(index: usize) -> Option<Watchpoint> {
+    let mut list = WATCHPOINTS.lock().unwrap();
+    if index >= list.len() {
+        return None;
+    }
+    let wp = list.remove(index);
+    ACTIVE.store(!list.is_empty(), Ordering::Relaxed);
+    Some(wp)
+}
+
+/// Removes the first watchpoint exactly matching the given range and kind.
+pub fn remove_matching(start: u32, len: u32, kind: WatchKind) -> bool {
+    let mut list = WATCHPOINTS.lock().unwrap();
+    let Some(index) = list.iter().position(|wp| wp.start == start && wp.len == len.max(1) && wp.kind == kind) else {
+        return false;
+    };
+    list.remove(index);
+    ACTIVE.store(!list.is_empty(), Ordering::Relaxed);
+    true
+}
+
+pub fn clear() {
+    WATCHPOINTS.lock().unwrap().clear();
+    ACTIVE.store(false, Ordering::Relaxed);
+}
+
+pub fn list() -> Vec<Watchpoint> {
+    WATCHPOINTS.lock().unwrap().clone()
+}
+
+/// Parses `[r|w|c]:ADDR[+LEN]`, e.g. `w:0x16A+4`, `c:$910+32` or `c:Ticks`.
+pub fn parse_spec(spec: &str) -> Option<(u32, u32, WatchKind)> {
+    let (kind, rest) = match spec.split_once(':') {
+        Some(("r", rest)) => (WatchKind::Read, rest),
+        Some(("w", rest)) => (WatchKind::Write, rest),
+        Some(("c", rest)) => (WatchKind::Change, rest),
+        Some(_) => return None,
+        None => (WatchKind::Write, spec),
+    };
+    let (addr, len) = match rest.split_once('+') {
+        Some((addr, len)) => (parse_symbolic(addr)?, parse_address(len)?),
+        // A low-memory global watched by name covers the whole global
+        None => match lowmem::by_name(rest) {
+            Some(global) => (global.addr, global.size),
+            None => (parse_symbolic(rest)?, 1),
+        },
+    };
+    Some((addr, len, kind))
+}
+
+fn record_hit(hit: WatchHit) {
+    warn!("{}", hit);
+    *LAST_HIT.lock().unwrap() = Some(hit.clone());
+    let mut pending = PENDING_HIT.lock().unwrap();
+    // Keep the first hit of the instruction; that's the one we stop on
+    if pending.is_none() {
+        *pending = Some(hit);
+        HIT_PENDING.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn check_read(addr: u32, size: u32, value: u32, pc: u32) {
+    let hit = WATCHPOINTS.lock().unwrap().iter()
+        .any(|wp| wp.kind == WatchKind::Read && wp.overlaps(addr, size));
+    if hit {
+        record_hit(WatchHit { kind: WatchKind::Read, addr, size, pc, old: value, new: value });
+    }
+}
+
+pub fn check_write(addr: u32, size: u32, old: u32, new: u32, pc: u32) {
+    let kind = WATCHPOINTS.lock().unwrap().iter()
+        .filter(|wp| wp.overlaps(addr, size))
+        .find(|wp| wp.kind == WatchKind::Write || (wp.kind == WatchKind::Change && wp.changes(addr, size, old, new)))
+        .map(|wp| wp.kind);
+    if let Some(kind) = kind {
+        record_hit(WatchHit { kind, addr, size, pc, old, new });
+    }
+}
+
+/// Returns the watchpoint hit by the last executed instruction, if any.
+pub fn take_hit() -> Option<WatchHit> {
+    if !HIT_PENDING.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    PENDING_HIT.lock().unwrap().take()
+}
+
+/// Returns (and forgets) the most recent hit, for reporting the stop reason to a remote debugger.
+pub fn take_last_hit() -> Option<WatchHit> {
+    LAST_HIT.lock().unwrap().take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_spec_kinds_and_lengths() {
+        assert_eq!(parse_spec("w:0x16A+4"), Some((0x16A, 4, WatchKind::Write)));
+        assert_eq!(parse_spec("r:$910+32"), Some((0x910, 32, WatchKind::Read)));
+        assert_eq!(parse_spec("c:0x100"), Some((0x100, 1, WatchKind::Change)));
+        assert_eq!(parse_spec("0x200+2"), Some((0x200, 2, WatchKind::Write)));
+    }
+
+    #[test]
+    fn parse_spec_rejects_bad_input() {
+        assert_eq!(parse_spec("x:0x100"), None);
+        assert_eq!(parse_spec("w:"), None);
+        assert_eq!(parse_spec("w:0x100+"), None);
+    }
+
+    #[test]
+    fn overlap_at_range_edges() {
+        let wp = Watchpoint { start: 0x100, len: 4, kind: WatchKind::Write };
+        assert!(wp.overlaps(0x0FF, 2));
+        assert!(wp.overlaps(0x103, 1));
+        assert!(!wp.overlaps(0x104, 4));
+        assert!(!wp.overlaps(0x0FC, 4));
+    }
+
+    #[test]
+    fn change_only_counts_watched_bytes() {
+        let wp = Watchpoint { start: 0x101, len: 1, kind: WatchKind::Change };
+        assert!(!wp.changes(0x100, 2, 0x1234, 0xFF34));
+        assert!(wp.changes(0x100, 2, 0x1234, 0x1235));
+        assert!(wp.changes(0x0FE, 4, 0x00001200, 0x00001201));
+        assert!(!wp.changes(0x0FE, 4, 0x00001200, 0xFFFF1200));
+    }
+}
@@ -0,0 +1,155 @@
+/* Window Manager and QuickDraw state.
+ *
+ * Walks WindowList through each WindowRecord's nextWindow link, and decodes
+ * the current GrafPort.  A WindowRecord begins with its GrafPort, so the
+ * same offsets serve both; regions are shown by their bounding boxes.
+ */
+
+use crate::memory::{peek_pstring, peek_u16, peek_u32, peek_u8};
+
+const WINDOW_LIST: u32 = 0x9D6;
+const CURRENT_A5: u32 = 0x904;
+// GrafPort offsets
+const PORT_BITS: u32 = 2;
+const PORT_RECT: u32 = 16;
+const VIS_RGN: u32 = 24;
+const CLIP_RGN: u32 = 28;
+const PN_LOC: u32 = 48;
+const PN_SIZE: u32 = 52;
+const PN_MODE: u32 = 56;
+const PN_PAT: u32 = 58;
+const PN_VIS: u32 = 66;
+const TX_FONT: u32 = 68;
+const TX_FACE: u32 = 70;
+const TX_MODE: u32 = 72;
+const TX_SIZE: u32 = 74;
+// WindowRecord offsets past the port
+const WINDOW_KIND: u32 = 108;
+const VISIBLE: u32 = 110;
+const HILITED: u32 = 111;
+const GO_AWAY_FLAG: u32 = 112;
+const STRUC_RGN: u32 = 114;
+const UPDATE_RGN: u32 = 122;
+const TITLE_HANDLE: u32 = 134;
+const NEXT_WINDOW: u32 = 144;
+const REF_CON: u32 = 152;
+const MAX_WINDOWS: usize = 256;
+
+fn rect(addr: u32) -> String {
+    let v = |i| peek_u16(addr + i) as i16;
+    format!("({},{},{},{})", v(0), v(2), v(4), v(6))
+}
+
+/// A region's bounding box, from its handle.
+fn region(handle: u32) -> String {
+    let handle = handle & 0xFFFFFF;
+    let rgn = if handle == 0 { 0 } else { peek_u32(handle) & 0xFFFFFF };
+    if rgn == 0 {
+        return "nil".to_string();
+    }
+    // rgnSize is 10 for a plain rectangle
+    let shape = if peek_u16(rgn) == 10 { "" } else { " complex" };
+    format!("{}{}", rect(rgn + 2), shape)
+}
+
+fn point(addr: u32) -> String {
+    format!("({},{})", peek_u16(addr) as i16, peek_u16(addr + 2) as i16)
+}
+
+fn kind_name(kind: i16) -> &'static str {
+    match kind {
+        k if k < 0 => "desk accessory",
+        2 => "dialog",
+        8 => "user",
+        _ => "system",
+    }
+}
+
+fn title(window: u32) -> String {
+    let handle = peek_u32(window + TITLE_HANDLE) & 0xFFFFFF;
+    let text = if handle == 0 { 0 } else { peek_u32(handle) & 0xFFFFFF };
+    if text == 0 { String::new() } else { peek_pstring(text) }
+}
+
+/// The windows in WindowList, frontmost first.
+pub fn window_list() -> Vec<u32> {
+    let mut windows = Vec::new();
+    let mut window = peek_u32(WINDOW_LIST) & 0xFFFFFF;
+    while window != 0 && windows.len() < MAX_WINDOWS {
+        windows.push(window);
+        window = peek_u32(window + NEXT_WINDOW) & 0xFFFFFF;
+    }
+    windows
+}
+
+/// One line describing a WindowRecord, with a second for its regions.
+pub fn describe_window(window: u32) -> String {
+    let kind = peek_u16(window + WINDOW_KIND) as i16;
+    let flag = |offset, name| if peek_u8(window + offset) != 0 { name } else { "" };
+    format!(
+        "0x{:06X}  \"{}\"  kind {} ({})  {} {} {}  refCon ${:08X}\n    portRect {}  visRgn {}  clipRgn {}  strucRgn {}  updateRgn {}",
+        window, title(window), kind, kind_name(kind),
+        flag(VISIBLE, "visible"), flag(HILITED, "hilited"), flag(GO_AWAY_FLAG, "goAway"),
+        peek_u32(window + REF_CON),
+        rect(window + PORT_RECT), region(peek_u32(window + VIS_RGN)), region(peek_u32(window + CLIP_RGN)),
+        region(peek_u32(window + STRUC_RGN)), region(peek_u32(window + UPDATE_RGN))
+    )
+}
+
+/// The current port: QuickDraw's globals pointer at (A5) points at thePort.
+pub fn the_port() -> u32 {
+    let a5 = peek_u32(CURRENT_A5) & 0xFFFFFF;
+    if a5 == 0 {
+        return 0;
+    }
+    let globals = peek_u32(a5) & 0xFFFFFF;
+    if globals == 0 { 0 } else { peek_u32(globals) & 0xFFFFFF }
+}
+
+/// A GrafPort's bitmap, rectangles, regions, pen and text settings.
+pub fn describe_port(port: u32) -> String {
+    let pattern: Vec<String> = (0..8).map(|i| format!("{:02X}", peek_u8(port + PN_PAT + i))).collect();
+    format!(
+        "GrafPort 0x{:06X}\n  portBits base 0x{:06X} rowBytes {} bounds {}\n  portRect {}  visRgn {}  clipRgn {}\n  \
+         pen loc {} size {} mode {} pat {} vis {}\n  text font {} face ${:02X} mode {} size {}",
+        port,
+        peek_u32(port + PORT_BITS) & 0xFFFFFF, peek_u16(port + PORT_BITS + 4) & 0x3FFF, rect(port + PORT_BITS + 6),
+        rect(port + PORT_RECT), region(peek_u32(port + VIS_RGN)), region(peek_u32(port + CLIP_RGN)),
+        point(port + PN_LOC), point(port + PN_SIZE), peek_u16(port + PN_MODE), pattern.join(""), peek_u16(port + PN_VIS) as i16,
+        peek_u16(port + TX_FONT), peek_u8(port + TX_FACE), peek_u16(port + TX_MODE), peek_u16(port + TX_SIZE)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::poke_u8;
+
+    fn put(addr: u32, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            assert!(poke_u8(addr + i as u32, b));
+        }
+    }
+
+    #[test]
+    fn describe_window_record() {
+        let window = 0x34000;
+        put(window + PORT_RECT, b"\x00\x00\x00\x00\x00\x64\x00\xC8");
+        put(window + VIS_RGN, b"\x00\x03\x42\x00");
+        put(window + WINDOW_KIND, b"\x00\x08\x01\x00\x01");
+        put(window + STRUC_RGN, b"\x00\x03\x42\x04");
+        put(window + TITLE_HANDLE, b"\x00\x03\x43\x00");
+        put(window + REF_CON, b"\x12\x34\x56\x78");
+        // Region handles, a rectangular and a complex region, the title
+        put(0x34200, b"\x00\x03\x42\x10\x00\x03\x42\x20");
+        put(0x34210, b"\x00\x0A\x00\x01\x00\x02\x00\x03\x00\x04");
+        put(0x34220, b"\x00\x1C\x00\x05\x00\x06\x00\x07\x00\x08");
+        put(0x34300, b"\x00\x03\x43\x10");
+        put(0x34310, b"\x04Test");
+        assert_eq!(
+            describe_window(window),
+            "0x034000  \"Test\"  kind 8 (user)  visible  goAway  refCon $12345678\n    \
+             portRect (0,0,100,200)  visRgn (1,2,3,4)  clipRgn nil  strucRgn (5,6,7,8) complex  updateRgn nil"
+        );
+    }
+}